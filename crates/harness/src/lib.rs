@@ -0,0 +1,395 @@
+//! Test harness running the services against an embedded MQTT broker.
+//!
+//! The broker, the proxy and the services only listen on localhost, on ports picked by the
+//! system, so that the tests can run in parallel.
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+pub use rumqttc::v5::mqttbytes::v5::Publish;
+use rumqttc::{
+    v5::{
+        mqttbytes::{v5::Packet, QoS},
+        AsyncClient, Event, MqttOptions,
+    },
+    TlsConfiguration, Transport,
+};
+use rumqttd::{ConnectionSettings, RouterConfig, ServerSettings, TlsConfig};
+use tokio::{
+    io::copy_bidirectional,
+    net::TcpStream as AsyncTcpStream,
+    sync::{mpsc, watch},
+    time::timeout,
+};
+
+/// How long to wait for a listener to accept connections.
+const STARTUP: Duration = Duration::from_secs(10);
+
+/// A port free at the time of the call.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port()
+}
+
+fn wait_for(port: u16) {
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(started.elapsed() < STARTUP, "nothing listens on {port}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// A directory removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// # Panics
+    /// Panics if the directory can't be created.
+    #[must_use]
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "rpi-harness-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).expect("can't create the temporary directory");
+        Self(path)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Default for TempDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A certificate authority and a certificate it signed for `localhost`, as PEM files.
+pub struct Certificates {
+    pub ca: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    _dir: TempDir,
+}
+
+impl Certificates {
+    /// # Panics
+    /// Panics if the certificates can't be generated or written.
+    #[must_use]
+    pub fn generate() -> Self {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "rpi-harness CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+
+        let dir = TempDir::new();
+        let write = |name: &str, pem: String| {
+            let path = dir.path().join(name);
+            fs::write(&path, pem).unwrap();
+            path
+        };
+        Self {
+            ca: write("ca.pem", ca.pem()),
+            cert: write("cert.pem", cert.pem()),
+            key: write("key.pem", key.serialize_pem()),
+            _dir: dir,
+        }
+    }
+}
+
+/// An MQTT v5 broker running in the background until the end of the test process.
+pub struct Broker {
+    pub port: u16,
+    /// Port of the TLS listener, if any.
+    pub tls_port: Option<u16>,
+}
+
+impl Broker {
+    /// Start a broker with a plain listener.
+    #[must_use]
+    pub fn start() -> Self {
+        Self::spawn(None)
+    }
+
+    /// Start a broker with a plain listener and a TLS one using `certificates`.
+    #[must_use]
+    pub fn start_tls(certificates: &Certificates) -> Self {
+        Self::spawn(Some(TlsConfig::Rustls {
+            capath: None,
+            certpath: certificates.cert.display().to_string(),
+            keypath: certificates.key.display().to_string(),
+        }))
+    }
+
+    fn spawn(tls: Option<TlsConfig>) -> Self {
+        let port = free_port();
+        let tls_port = tls.as_ref().map(|_| free_port());
+        let mut servers = HashMap::from([("plain".to_string(), server(port, None))]);
+        if let (Some(tls_port), Some(tls)) = (tls_port, tls) {
+            servers.insert("tls".to_string(), server(tls_port, Some(tls)));
+        }
+        let config = rumqttd::Config {
+            router: RouterConfig {
+                max_connections: 100,
+                max_outgoing_packet_count: 200,
+                max_segment_size: 1024 * 1024,
+                max_segment_count: 10,
+                ..RouterConfig::default()
+            },
+            v5: Some(servers),
+            ..rumqttd::Config::default()
+        };
+        thread::spawn(move || {
+            rumqttd::Broker::new(config)
+                .start()
+                .expect("the broker stopped");
+        });
+
+        wait_for(port);
+        if let Some(tls_port) = tls_port {
+            wait_for(tls_port);
+        }
+        Self { port, tls_port }
+    }
+}
+
+fn server(port: u16, tls: Option<TlsConfig>) -> ServerSettings {
+    ServerSettings {
+        name: format!("v5-{port}"),
+        listen: SocketAddr::from(([127, 0, 0, 1], port)),
+        tls,
+        next_connection_delay_ms: 1,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 5000,
+            max_payload_size: 20480,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    }
+}
+
+/// A TCP proxy in front of the broker, whose connections can be cut as if the network dropped.
+/// The broker keeps running, with the sessions and the retained messages.
+pub struct Proxy {
+    pub port: u16,
+    cut: watch::Sender<usize>,
+}
+
+impl Proxy {
+    /// Forward the connections to the local `upstream` port.
+    ///
+    /// # Panics
+    /// Panics if the proxy can't listen.
+    pub async fn start(upstream: u16) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (cut, cuts) = watch::channel(0);
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let mut cuts = cuts.clone();
+                cuts.mark_unchanged();
+                tokio::spawn(async move {
+                    let Ok(mut broker) = AsyncTcpStream::connect(("127.0.0.1", upstream)).await
+                    else {
+                        return;
+                    };
+                    tokio::select! {
+                        _ = copy_bidirectional(&mut client, &mut broker) => {}
+                        _ = cuts.changed() => {}
+                    }
+                });
+            }
+        });
+        Self { port, cut }
+    }
+
+    /// Close the open connections, new ones are still accepted.
+    pub fn cut(&self) {
+        self.cut.send_modify(|cuts| *cuts += 1);
+    }
+}
+
+/// A client subscribed to topics, collecting what it receives.
+pub struct Subscriber {
+    client: AsyncClient,
+    publishes: mpsc::UnboundedReceiver<Publish>,
+}
+
+impl Subscriber {
+    /// Subscribe to `filter` on the plain listener of the broker at `port`.
+    ///
+    /// # Panics
+    /// Panics if the subscription isn't acknowledged.
+    pub async fn connect(port: u16, filter: &str) -> Self {
+        Self::connect_with(MqttOptions::new(client_id(), "127.0.0.1", port), filter).await
+    }
+
+    /// Subscribe to `filter` on the TLS listener of the broker at `port`.
+    ///
+    /// # Panics
+    /// Panics if the CA can't be read or the subscription isn't acknowledged.
+    pub async fn connect_tls(port: u16, certificates: &Certificates, filter: &str) -> Self {
+        let mut options = MqttOptions::new(client_id(), "localhost", port);
+        options.set_transport(Transport::tls_with_config(TlsConfiguration::Simple {
+            ca: fs::read(&certificates.ca).unwrap(),
+            alpn: None,
+            client_auth: None,
+        }));
+        Self::connect_with(options, filter).await
+    }
+
+    async fn connect_with(mut options: MqttOptions, filter: &str) -> Self {
+        options.set_keep_alive(Duration::from_secs(5));
+        let (client, mut eventloop) = AsyncClient::new(options, 50);
+        let (subscribed_tx, mut subscribed) = mpsc::unbounded_channel();
+        let (tx, publishes) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::SubAck(_))) => {
+                        let _ = subscribed_tx.send(());
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if tx.send(publish).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+                }
+            }
+        });
+        client.subscribe(filter, QoS::AtLeastOnce).await.unwrap();
+        timeout(STARTUP, subscribed.recv())
+            .await
+            .expect("subscription not acknowledged");
+        Self { client, publishes }
+    }
+
+    /// The next message, if one arrives within `wait`.
+    pub async fn next(&mut self, wait: Duration) -> Option<Publish> {
+        timeout(wait, self.publishes.recv()).await.ok().flatten()
+    }
+
+    /// The next message on `topic`, skipping the others, if one arrives within `wait`.
+    pub async fn next_on(&mut self, topic: &str, wait: Duration) -> Option<Publish> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let publish = timeout(
+                deadline.saturating_duration_since(tokio::time::Instant::now()),
+                self.publishes.recv(),
+            )
+            .await
+            .ok()
+            .flatten()?;
+            if publish.topic == topic.as_bytes() {
+                return Some(publish);
+            }
+        }
+    }
+
+    /// The client, to publish commands.
+    #[must_use]
+    pub const fn client(&self) -> &AsyncClient {
+        &self.client
+    }
+}
+
+fn client_id() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "harness-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Run `binary` to completion with `args` and only the variables of `vars` set, from an empty
+/// directory.
+///
+/// # Panics
+/// Panics if the binary can't be run.
+#[must_use]
+pub fn run(binary: &str, args: &[&str], vars: &[(&str, String)]) -> Output {
+    let dir = TempDir::new();
+    Command::new(binary)
+        .args(args)
+        .current_dir(dir.path())
+        .env_clear()
+        .envs(vars.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap_or_else(|e| panic!("can't run {binary}: {e}"))
+}
+
+/// A service binary, killed on drop.
+pub struct Service {
+    child: Child,
+    _dir: TempDir,
+}
+
+impl Service {
+    /// Run `binary` with only the variables of `vars` set, from an empty directory so that no
+    /// `.env` is loaded.
+    ///
+    /// # Panics
+    /// Panics if the binary can't be run.
+    #[must_use]
+    pub fn start(binary: &str, vars: &[(&str, String)]) -> Self {
+        let dir = TempDir::new();
+        let child = Command::new(binary)
+            .current_dir(dir.path())
+            .env_clear()
+            .envs(vars.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("can't run {binary}: {e}"));
+        Self { child, _dir: dir }
+    }
+
+    /// Kill the service without letting it disconnect, as a power cut would.
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
@@ -0,0 +1,382 @@
+//! Where the state of the sensor comes from: a digital input watched with interrupts, or a
+//! light level polled through an analog-to-digital converter or a lux sensor.
+use rpi_gpio::{
+    adc::{Adc, Ads1115, Calibration, LightLevel, LightMeter, Mcp3008, Unit},
+    light::Watcher,
+    lux::{Bh1750, Tsl2561},
+    metrics::Metrics,
+    sensor::{Blocking, Sensor, SensorError},
+    status::SharedStatus,
+    ReadingError,
+};
+use rppal::{
+    i2c::I2c,
+    spi::{self, Bus, SlaveSelect, Spi},
+};
+use serde_json::{json, Value};
+use tokio::time::{interval, Instant, Interval, MissedTickBehavior};
+use tracing::error;
+
+use std::{env, str::FromStr, time::Duration};
+
+const MODE: &str = "LIGHT_MODE";
+const ADC_CHANNEL: &str = "LIGHT_ADC_CHANNEL";
+const ADC_CHIP_SELECT: &str = "LIGHT_ADC_CHIP_SELECT";
+const ADC_REFERENCE: &str = "LIGHT_ADC_REFERENCE";
+const I2C_BUS: &str = "LIGHT_I2C_BUS";
+const I2C_ADDRESS: &str = "LIGHT_I2C_ADDRESS";
+const UNIT: &str = "LIGHT_UNIT";
+const FULL_SCALE: &str = "LIGHT_FULL_SCALE";
+const THRESHOLD_ON: &str = "LIGHT_THRESHOLD_ON";
+const THRESHOLD_OFF: &str = "LIGHT_THRESHOLD_OFF";
+const MIN_CHANGE: &str = "LIGHT_MIN_CHANGE";
+const POLL_MS: &str = "LIGHT_POLL_MS";
+
+/// Variables renamed when the lux sensors were added, still read when the new name isn't set.
+const RENAMED: [(&str, &str); 3] = [
+    (MODE, "LIGHT_ADC"),
+    (I2C_BUS, "LIGHT_ADC_BUS"),
+    (I2C_ADDRESS, "LIGHT_ADC_ADDRESS"),
+];
+
+/// Spi clock, well below the 1.35 MHz limit of the MCP3008 at 2.7 V.
+const SPI_CLOCK: u32 = 1_000_000;
+
+/// State of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct State {
+    pub active: bool,
+    /// Light level of a light meter.
+    pub level: Option<f32>,
+}
+
+/// How the state is measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// A digital input, such as a light sensor module or a door switch.
+    #[default]
+    Digital,
+    Mcp3008,
+    Ads1115,
+    Bh1750,
+    Tsl2561,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "digital" => Ok(Self::Digital),
+            "mcp3008" => Ok(Self::Mcp3008),
+            "ads1115" => Ok(Self::Ads1115),
+            "bh1750" => Ok(Self::Bh1750),
+            "tsl2561" => Ok(Self::Tsl2561),
+            _ => Err(format!(
+                "unknown mode {s}, expected digital, mcp3008, ads1115, bh1750 or tsl2561"
+            )),
+        }
+    }
+}
+
+impl Mode {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Digital => "digital",
+            Self::Mcp3008 => "mcp3008",
+            Self::Ads1115 => "ads1115",
+            Self::Bh1750 => "bh1750",
+            Self::Tsl2561 => "tsl2561",
+        }
+    }
+
+    /// Whether the sensor measures lux by itself.
+    const fn lux(self) -> bool {
+        matches!(self, Self::Bh1750 | Self::Tsl2561)
+    }
+
+    const fn default_address(self) -> u8 {
+        match self {
+            Self::Bh1750 => Bh1750::<I2c>::DEFAULT_ADDRESS,
+            Self::Tsl2561 => Tsl2561::<I2c>::DEFAULT_ADDRESS,
+            _ => Ads1115::<I2c>::DEFAULT_ADDRESS,
+        }
+    }
+}
+
+/// The variable `name`, or the one it was renamed from.
+fn var(name: &str) -> Result<String, env::VarError> {
+    env::var(name).or_else(|e| {
+        RENAMED
+            .iter()
+            .find(|(new, _)| *new == name)
+            .map_or(Err(e), |(_, old)| env::var(old))
+    })
+}
+
+fn parse<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    var(name).map_or(Ok(default), |value| {
+        value.parse().map_err(|_| format!("{name} is invalid"))
+    })
+}
+
+/// Settings of a light meter: a converter reading an LDR or a photodiode, or a lux sensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeterConfig {
+    mode: Mode,
+    channel: u8,
+    /// Chip select of the MCP3008 on the SPI0 bus, 0 or 1.
+    chip_select: u8,
+    /// Voltage powering the divider read by the ADS1115.
+    reference: f32,
+    /// I2C bus and address of the ADS1115 or the lux sensor.
+    bus: u8,
+    address: u8,
+    calibration: Calibration,
+    /// Smallest level change published when there's no light change.
+    min_change: f32,
+    period: Duration,
+}
+
+impl MeterConfig {
+    /// The settings of the light meter, `None` in digital mode. `inverted` is whether the
+    /// voltage of a converter drops as the light increases, ignored by the lux sensors.
+    ///
+    /// # Errors
+    /// Returns a message if a variable is invalid.
+    pub fn from_env(inverted: bool) -> Result<Option<Self>, String> {
+        let mode = var(MODE)
+            .map_or_else(|_| Ok(Mode::default()), |value| value.parse::<Mode>())
+            .map_err(|e| format!("{MODE} is invalid: {e}"))?;
+        if mode == Mode::Digital {
+            return Ok(None);
+        }
+        let unit = if mode.lux() {
+            Unit::Lux
+        } else {
+            env::var(UNIT)
+                .map_or_else(|_| Ok(Unit::default()), |value| value.parse())
+                .map_err(|e| format!("{UNIT} is invalid: {e}"))?
+        };
+        let full_scale = parse(FULL_SCALE, 100.0)?;
+        let on = parse(THRESHOLD_ON, full_scale / 2.0)?;
+        let chip_select = parse(ADC_CHIP_SELECT, 0)?;
+        if chip_select > 1 {
+            return Err(format!("{ADC_CHIP_SELECT} is invalid: expected 0 or 1"));
+        }
+        let reference = parse(ADC_REFERENCE, 3.3)?;
+        if reference <= 0.0 || reference > Ads1115::<I2c>::RANGE {
+            return Err(format!(
+                "{ADC_REFERENCE} is invalid: expected a voltage up to {}",
+                Ads1115::<I2c>::RANGE
+            ));
+        }
+        let address = var(I2C_ADDRESS).map_or_else(
+            |_| Ok(mode.default_address()),
+            |value| {
+                let value = value.trim_start_matches("0x");
+                u8::from_str_radix(value, 16).map_err(|_| format!("{I2C_ADDRESS} is invalid"))
+            },
+        )?;
+
+        Ok(Some(Self {
+            mode,
+            channel: parse(ADC_CHANNEL, 0)?,
+            chip_select,
+            reference,
+            bus: parse(I2C_BUS, 1)?,
+            address,
+            calibration: Calibration {
+                unit,
+                full_scale,
+                inverted: inverted && !mode.lux(),
+                on,
+                // A few percent below, so that the state doesn't chatter around the threshold.
+                off: parse(THRESHOLD_OFF, on * 0.95)?,
+            },
+            min_change: parse(MIN_CHANGE, full_scale / 100.0)?,
+            period: Duration::from_millis(parse(POLL_MS, 1000)?),
+        }))
+    }
+
+    pub const fn unit(&self) -> Unit {
+        self.calibration.unit
+    }
+
+    /// Model of the converter or sensor.
+    pub const fn model(&self) -> &'static str {
+        self.mode.name()
+    }
+
+    pub fn summary(&self) -> Value {
+        let mut summary = json!({
+            "model": self.model(),
+            "unit": self.calibration.unit.key(),
+            "threshold_on": self.calibration.on,
+            "threshold_off": self.calibration.off,
+            "min_change": self.min_change,
+            "poll_ms": self.period.as_millis(),
+        });
+        if self.mode == Mode::Mcp3008 {
+            summary["chip_select"] = json!(self.chip_select);
+        } else {
+            if self.mode == Mode::Ads1115 {
+                summary["reference"] = json!(self.reference);
+            }
+            summary["i2c_bus"] = json!(self.bus);
+            summary["i2c_address"] = json!(format!("{:#04x}", self.address));
+        }
+        if !self.mode.lux() {
+            summary["channel"] = json!(self.channel);
+            summary["full_scale"] = json!(self.calibration.full_scale);
+        }
+        summary
+    }
+
+    fn meter(&self) -> Result<Meter, ReadingError> {
+        let sensor: Box<dyn Sensor + Send> = match self.mode {
+            Mode::Digital => unreachable!("there's no meter in digital mode"),
+            Mode::Mcp3008 => {
+                let chip_select = match self.chip_select {
+                    0 => SlaveSelect::Ss0,
+                    _ => SlaveSelect::Ss1,
+                };
+                let spi = Spi::new(Bus::Spi0, chip_select, SPI_CLOCK, spi::Mode::Mode0)?;
+                self.light_meter(Box::new(Mcp3008::new(spi, self.channel)))
+            }
+            Mode::Ads1115 => self.light_meter(Box::new(Ads1115::new(
+                I2c::with_bus(self.bus)?,
+                self.address,
+                self.channel,
+                self.reference,
+            ))),
+            Mode::Bh1750 => Box::new(Blocking::new(Bh1750::new(
+                I2c::with_bus(self.bus)?,
+                self.address,
+            ))),
+            Mode::Tsl2561 => Box::new(Blocking::new(Tsl2561::new(
+                I2c::with_bus(self.bus)?,
+                self.address,
+            ))),
+        };
+        Ok(Meter {
+            sensor,
+            calibration: self.calibration,
+        })
+    }
+
+    fn light_meter(&self, adc: Box<dyn Adc + Send>) -> Box<dyn Sensor + Send> {
+        Box::new(LightMeter::new(adc, self.calibration))
+    }
+
+    /// Open the light meter and read the initial level.
+    ///
+    /// # Errors
+    /// Returns a `SensorError` if the meter can't be opened or read.
+    pub async fn open(&self) -> Result<Input, SensorError> {
+        let mut meter = self.meter()?;
+        let reading = meter.read(None).await?;
+
+        let mut interval = interval(self.period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, and the initial level was just read.
+        interval.tick().await;
+        Ok(Input::Level {
+            meter,
+            interval,
+            min_change: self.min_change,
+            state: State {
+                active: reading.light,
+                level: Some(reading.level),
+            },
+        })
+    }
+}
+
+/// A light sensor, with the calibration telling whether there's light.
+pub struct Meter {
+    sensor: Box<dyn Sensor + Send>,
+    calibration: Calibration,
+}
+
+impl Meter {
+    /// Read the level, knowing whether there was light before.
+    async fn read(&mut self, previous: Option<bool>) -> Result<LightLevel, SensorError> {
+        let measurements = self.sensor.read().await?;
+        // The level comes first, a converter also tells whether there's light.
+        let level = measurements
+            .value(self.sensor.quantities()[0])
+            .expect("a light meter measures a level");
+        Ok(LightLevel {
+            level,
+            light: self.calibration.light(level, previous),
+        })
+    }
+}
+
+/// Source of the state changes.
+pub enum Input {
+    Digital(Watcher),
+    Level {
+        meter: Meter,
+        interval: Interval,
+        min_change: f32,
+        /// Last reported state.
+        state: State,
+    },
+}
+
+impl Input {
+    pub const fn state(&self) -> State {
+        match self {
+            Self::Digital(watcher) => State {
+                active: watcher.level(),
+                level: None,
+            },
+            Self::Level { state, .. } => *state,
+        }
+    }
+
+    /// Wait for the next state change, `None` if the input can't be watched anymore.
+    ///
+    /// The level of a light meter is polled, and reported when the light changes or when it
+    /// moved by more than the minimum change.
+    pub async fn changed(&mut self, metrics: &Metrics, status: &SharedStatus) -> Option<State> {
+        match self {
+            Self::Digital(watcher) => watcher.changed().await.map(|active| State {
+                active,
+                level: None,
+            }),
+            Self::Level {
+                meter,
+                interval,
+                min_change,
+                state,
+            } => loop {
+                interval.tick().await;
+                let started = Instant::now();
+                let result = meter.read(Some(state.active)).await;
+                metrics.read(started.elapsed(), result.as_ref().err());
+                match result {
+                    Ok(reading) => {
+                        metrics.light_level(reading.level);
+                        let moved = state
+                            .level
+                            .is_none_or(|level| (reading.level - level).abs() >= *min_change);
+                        if reading.light != state.active || moved {
+                            *state = State {
+                                active: reading.light,
+                                level: Some(reading.level),
+                            };
+                            return Some(*state);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Can't read the light level: {}", e);
+                        status.lock().unwrap().failure();
+                    }
+                }
+            },
+        }
+    }
+}
@@ -1,20 +1,61 @@
-use rpi_gpio::{light::read, tls::load_certs};
+mod input;
+
+use input::{Input, MeterConfig, State};
+use rpi_gpio::{
+    binary::{parse_value, BinarySensor},
+    http::{serve, Response},
+    light::{watch, watch_simulated, InputOptions, WatchOptions},
+    metrics::Metrics,
+    sensor::SensorError,
+    simulated::Script,
+    status::{Status, REDACTED},
+    tls::load_certs,
+    Backend, Pull,
+};
+#[cfg(feature = "cdev")]
+use rpi_gpio::{cdev::LineSpec, light::watch_line};
 use rumqttc::{
-    v5::{mqttbytes::QoS, AsyncClient, Event, MqttOptions},
+    v5::{
+        mqttbytes::{
+            v5::{LastWill, Packet, PublishProperties},
+            QoS,
+        },
+        AsyncClient, Event, MqttOptions,
+    },
     Transport,
 };
-use serde_json::json;
-use tokio::time::{interval, sleep};
+use serde_json::{json, Value};
+use tokio::time::sleep;
 use tracing::{debug, error, info, level_filters::LevelFilter, trace};
 use tracing_subscriber::EnvFilter;
 
-use std::{env, error::Error, path::Path, time::Duration};
+use std::{
+    env,
+    error::Error,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 fn not_set(env: &str) -> String {
     format!("{env} not set")
 }
 
+const GPIO_BACKEND: &str = "LIGHT_GPIO_BACKEND";
 const PIN: &str = "LIGHT_PIN";
+#[cfg(feature = "cdev")]
+const LINE: &str = "LIGHT_LINE";
+const PULL: &str = "LIGHT_PULL";
+const INVERTED: &str = "LIGHT_INVERTED";
+const DEBOUNCE_MS: &str = "LIGHT_DEBOUNCE_MS";
+const STABLE_MS: &str = "LIGHT_STABLE_MS";
+const SIMULATION_SCRIPT: &str = "LIGHT_SIMULATION_SCRIPT";
+const SENSOR_NAME: &str = "LIGHT_SENSOR_NAME";
+const PAYLOAD_KEY: &str = "LIGHT_PAYLOAD_KEY";
+const PAYLOAD_ON: &str = "LIGHT_PAYLOAD_ON";
+const PAYLOAD_OFF: &str = "LIGHT_PAYLOAD_OFF";
+const DEVICE_CLASS: &str = "LIGHT_DEVICE_CLASS";
+const DISCOVERY_PREFIX: &str = "LIGHT_DISCOVERY_PREFIX";
 const MQTT_CLIENT_ID: &str = "LIGHT_MQTT_CLIENT_ID";
 const MQTT_IP: &str = "MQTT_IP";
 const MQTT_PORT: &str = "MQTT_PORT";
@@ -24,6 +65,43 @@ const MQTT_PASSWORD: &str = "MQTT_PASSWORD";
 const CERTIFICATE_AUTHORITY_PATH: &str = "CERTIFICATE_AUTHORITY_PATH";
 const MTLS_CERT_PATH: &str = "MTLS_CERT_PATH";
 const MTLS_PKEY_PATH: &str = "MTLS_PKEY_PATH";
+const HTTP_ADDR: &str = "LIGHT_HTTP_ADDR";
+
+/// Payloads of the availability topic, the offline one being the last will.
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// The JSON payload of `state`, with the light level of a light meter.
+fn payload(sensor: &BinarySensor, meter: Option<&MeterConfig>, state: State) -> Value {
+    let mut data = sensor.payload(state.active);
+    if let (Some(meter), Some(level)) = (meter, state.level) {
+        data[meter.unit().key()] = json!((level * 10.0).round() / 10.0);
+    }
+    data
+}
+
+fn publish_properties(pin: Option<u8>, model: &str, sensor: &BinarySensor) -> PublishProperties {
+    let mut user_properties = vec![
+        ("sensor_model".to_string(), model.to_string()),
+        (
+            "firmware_version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+    ];
+    if let Some(pin) = pin {
+        user_properties.push(("pin".to_string(), pin.to_string()));
+    }
+    if let Some(device_class) = &sensor.device_class {
+        user_properties.push(("device_class".to_string(), device_class.clone()));
+    }
+
+    PublishProperties {
+        payload_format_indicator: Some(1),
+        content_type: Some("application/json".to_string()),
+        user_properties,
+        ..Default::default()
+    }
+}
 
 #[allow(clippy::too_many_lines)]
 #[tokio::main(flavor = "current_thread")]
@@ -47,13 +125,74 @@ async fn main() -> Result<(), Box<dyn Error>> {
         env::var(MQTT_USERNAME).unwrap_or_else(|_| panic!("{}", not_set(MQTT_USERNAME)));
     let mqtt_password =
         env::var(MQTT_PASSWORD).unwrap_or_else(|_| panic!("{}", not_set(MQTT_PASSWORD)));
-    let pin = env::var(PIN)
-        .unwrap_or_else(|_| panic!("{}", not_set(PIN)))
-        .parse::<u8>()
-        .unwrap_or_else(|_| panic!("{PIN} is not a valid u16"));
+    let backend = env::var(GPIO_BACKEND)
+        .map_or_else(|_| Ok(Backend::default()), |value| value.parse::<Backend>())
+        .unwrap_or_else(|e| panic!("{GPIO_BACKEND} is invalid: {e}"));
+    let watch_options = WatchOptions {
+        input: InputOptions {
+            pull: env::var(PULL)
+                .map_or_else(|_| Ok(Pull::default()), |value| value.parse::<Pull>())
+                .unwrap_or_else(|e| panic!("{PULL} is invalid: {e}")),
+            inverted: env::var(INVERTED).is_ok_and(|value| {
+                value
+                    .parse::<bool>()
+                    .unwrap_or_else(|_| panic!("{INVERTED} is not a valid bool"))
+            }),
+        },
+        debounce: env::var(DEBOUNCE_MS).ok().map(|value| {
+            Duration::from_millis(
+                value
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("{DEBOUNCE_MS} is not a valid u64")),
+            )
+        }),
+        stable: Duration::from_millis(env::var(STABLE_MS).map_or(0, |value| {
+            value
+                .parse::<u64>()
+                .unwrap_or_else(|_| panic!("{STABLE_MS} is not a valid u64"))
+        })),
+    };
+    let script = env::var(SIMULATION_SCRIPT)
+        .map_or_else(|_| Ok(Script::default()), |value| value.parse::<Script>())
+        .unwrap_or_else(|e| panic!("{SIMULATION_SCRIPT} is invalid: {e}"));
+    let default_sensor = BinarySensor::default();
+    let sensor = BinarySensor {
+        name: env::var(SENSOR_NAME).unwrap_or(default_sensor.name),
+        key: env::var(PAYLOAD_KEY).unwrap_or(default_sensor.key),
+        on: env::var(PAYLOAD_ON).map_or(default_sensor.on, |value| parse_value(&value)),
+        off: env::var(PAYLOAD_OFF).map_or(default_sensor.off, |value| parse_value(&value)),
+        device_class: env::var(DEVICE_CLASS).ok().or(default_sensor.device_class),
+    };
+    let meter =
+        MeterConfig::from_env(watch_options.input.inverted).unwrap_or_else(|e| panic!("{e}"));
+    let model = meter.as_ref().map_or("digital", MeterConfig::model);
+    if let (Some(meter), Backend::Simulated) = (&meter, backend) {
+        panic!(
+            "{GPIO_BACKEND}=simulated only simulates the digital input, not the {}",
+            meter.model()
+        );
+    }
+    // Only the digital input is wired to a GPIO pin.
+    let pin = meter.is_none().then(|| {
+        env::var(PIN)
+            .unwrap_or_else(|_| panic!("{}", not_set(PIN)))
+            .parse::<u8>()
+            .unwrap_or_else(|_| panic!("{PIN} is not a valid u8"))
+    });
+    #[cfg(feature = "cdev")]
+    let line = pin.map(|pin| {
+        env::var(LINE)
+            .map_or_else(
+                |_| Ok(LineSpec::offset(u32::from(pin))),
+                |value| value.parse::<LineSpec>(),
+            )
+            .unwrap_or_else(|e| panic!("{LINE} is invalid: {e}"))
+    });
+    let discovery_prefix: Option<String> = env::var(DISCOVERY_PREFIX).ok();
     let ca_cert_path: Option<String> = env::var(CERTIFICATE_AUTHORITY_PATH).ok();
     let mtls_cert_path: Option<String> = env::var(MTLS_CERT_PATH).ok();
     let mtls_pkey_path: Option<String> = env::var(MTLS_PKEY_PATH).ok();
+    let http_addr: Option<String> = env::var(HTTP_ADDR).ok();
 
     let log_level_str = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
     println!("Using log level: {log_level_str}");
@@ -70,18 +209,113 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .compact()
         .init();
 
+    let mut summary = json!({
+        "client_id": client_id,
+        "mqtt_ip": mqtt_ip,
+        "mqtt_port": mqtt_port,
+        "mqtt_topic": mqtt_topic,
+        "mqtt_username": mqtt_username,
+        "mqtt_password": REDACTED,
+        "backend": backend.name(),
+        "pull": watch_options.input.pull.name(),
+        "inverted": watch_options.input.inverted,
+        "debounce_ms": watch_options.debounce.map(|debounce| debounce.as_millis()),
+        "stable_ms": watch_options.stable.as_millis(),
+        "sensor": {
+            "name": sensor.name,
+            "key": sensor.key,
+            "on": sensor.on,
+            "off": sensor.off,
+            "device_class": sensor.device_class,
+        },
+        "meter": meter.as_ref().map(MeterConfig::summary),
+        "discovery_prefix": discovery_prefix,
+        "tls": ca_cert_path.is_some(),
+        "mtls": mtls_cert_path.is_some() && mtls_pkey_path.is_some(),
+        "log_level": log_level_str,
+    });
+    if let Some(pin) = pin {
+        summary["pin"] = json!(pin);
+    }
+    let status = Status::shared(summary);
+    let metrics = Arc::new(Metrics::new());
+    if let Some(addr) = http_addr {
+        let status = status.clone();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let handler = move |path: &str| match path {
+                "/status" => Some(Response::json(&status.lock().unwrap().to_json())),
+                "/metrics" => Some(Response::prometheus(metrics.render())),
+                _ => None,
+            };
+            if let Err(e) = serve(&addr, handler).await {
+                error!("Status endpoint stopped: {}", e);
+            }
+        });
+    }
+
     let client_config = load_certs(ca_cert_path, mtls_pkey_path, mtls_cert_path).unwrap();
 
-    let mut interval = interval(Duration::from_secs(1));
-    let mut previous: Option<bool> = None;
+    let mut input = loop {
+        let started = Instant::now();
+        let result = match (&meter, backend) {
+            (Some(meter), _) => meter.open().await,
+            (None, Backend::Rppal) => watch(pin.expect("set in digital mode"), watch_options)
+                .map(Input::Digital)
+                .map_err(SensorError::from),
+            #[cfg(feature = "cdev")]
+            (None, Backend::Cdev) => {
+                watch_line(line.as_ref().expect("set in digital mode"), watch_options)
+                    .map(Input::Digital)
+                    .map_err(SensorError::from)
+            }
+            (None, Backend::Simulated) => {
+                Ok(Input::Digital(watch_simulated(&script, watch_options)))
+            }
+        };
+        metrics.read(started.elapsed(), result.as_ref().err());
+        match result {
+            Ok(input) => break input,
+            Err(e) => {
+                error!("Can't watch the {} sensor: {}", sensor.name, e);
+                status.lock().unwrap().failure();
+                sleep(Duration::from_secs(10)).await;
+            }
+        }
+    };
+    let mut state = input.state();
+    metrics.light(state.active);
+    if let Some(level) = state.level {
+        metrics.light_level(level);
+    }
+    status
+        .lock()
+        .unwrap()
+        .reading(payload(&sensor, meter.as_ref(), state));
+
+    let availability_topic = format!("{mqtt_topic}/availability");
+    let mut previous: Option<State> = None;
+    let mut first_connection = true;
     loop {
+        if first_connection {
+            first_connection = false;
+        } else {
+            metrics.reconnected();
+        }
         info!("Connecting to MQTT broker...");
 
         let mut mqttoptions = MqttOptions::new(&client_id, &mqtt_ip, mqtt_port);
         mqttoptions
             .set_keep_alive(Duration::from_secs(60))
             .set_clean_start(true)
-            .set_credentials(&mqtt_username, &mqtt_password);
+            .set_credentials(&mqtt_username, &mqtt_password)
+            .set_last_will(LastWill::new(
+                &availability_topic,
+                OFFLINE,
+                QoS::AtLeastOnce,
+                true,
+                None,
+            ));
 
         if let Some(config) = &client_config {
             info!("Using TLS");
@@ -90,9 +324,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         let (client, mut eventloop) = AsyncClient::new(mqttoptions, 50);
 
-        let event_loop_handle = tokio::spawn(async move {
+        let event_status = status.clone();
+        let mut event_loop_handle = tokio::spawn(async move {
             loop {
-                match eventloop.poll().await {
+                let event = eventloop.poll().await;
+                // Connected once the broker accepted the connection, not on the first poll.
+                let connected = match &event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => Some(true),
+                    Ok(_) => None,
+                    Err(_) => Some(false),
+                };
+                event_status.lock().unwrap().connection(
+                    connected,
+                    eventloop.pending.len() + usize::from(eventloop.state.inflight()),
+                );
+                match event {
                     Ok(Event::Outgoing(_) | Event::Incoming(_)) => {}
                     Err(e) => {
                         error!("Error in event loop: {:?}", e);
@@ -102,47 +348,67 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         });
 
-        loop {
-            debug!("Is there some light...");
-            match read(pin) {
-                Ok(light) => {
-                    if previous.is_some() && previous == Some(light) {
-                        trace!("No change detected");
-                    } else {
-                        previous = Some(light);
-                        let data = json!({
-                            "light": light,
-                        });
-                        debug!(
-                            "{}",
-                            if light {
-                                "there's light!"
-                            } else {
-                                "there's no light"
-                            }
-                        );
-                        match client
-                            .publish(&mqtt_topic, QoS::AtLeastOnce, false, data.to_string())
-                            .await
-                        {
-                            Ok(()) => {
-                                debug!("Data published!");
-                            }
-                            Err(e) => {
-                                error!("Failed to publish data: {}", e);
-                                break;
-                            }
-                        }
+        if let Err(e) = client
+            .publish(&availability_topic, QoS::AtLeastOnce, true, ONLINE)
+            .await
+        {
+            error!("Failed to publish the availability: {}", e);
+        }
+
+        if let Some(prefix) = &discovery_prefix {
+            let (topic, mut config) = sensor.discovery(prefix, &client_id, &mqtt_topic);
+            config["availability_topic"] = json!(availability_topic);
+            if let Err(e) = client
+                .publish(topic, QoS::AtLeastOnce, true, config.to_string())
+                .await
+            {
+                error!("Failed to publish the discovery config: {}", e);
+            }
+        }
+
+        let event_loop_result = loop {
+            if previous.is_some() && previous == Some(state) {
+                trace!("No change detected");
+            } else {
+                previous = Some(state);
+                let data = payload(&sensor, meter.as_ref(), state);
+                debug!("{} is {}", sensor.name, sensor.value(state.active));
+                let published = client
+                    .publish_with_properties(
+                        &mqtt_topic,
+                        QoS::AtLeastOnce,
+                        false,
+                        data.to_string(),
+                        publish_properties(pin, model, &sensor),
+                    )
+                    .await;
+                metrics.published(published.is_ok());
+                match published {
+                    Ok(()) => {
+                        debug!("Data published!");
+                    }
+                    Err(e) => {
+                        error!("Failed to publish data: {}", e);
+                        break event_loop_handle.await;
                     }
                 }
-                Err(e) => {
-                    error!("Is there some light? {:?}", e);
+            }
+
+            tokio::select! {
+                changed = input.changed(&metrics, &status) => {
+                    let Some(changed) = changed else {
+                        let message = format!("can't watch the {} sensor", sensor.name);
+                        return Err(message.into());
+                    };
+                    state = changed;
+                    metrics.light(state.active);
+                    status.lock().unwrap().reading(payload(&sensor, meter.as_ref(), state));
                 }
-            };
-            interval.tick().await;
-        }
+                result = &mut event_loop_handle => break result,
+            }
+        };
 
-        if event_loop_handle.await.is_err() {
+        if event_loop_result.is_err() {
             error!("Reconnecting after event loop failure...");
         }
 
@@ -0,0 +1,84 @@
+//! The light service on the simulated backend, against an embedded broker.
+use std::time::Duration;
+
+use harness::{run, Broker, Service, Subscriber};
+use serde_json::{json, Value};
+
+const TOPIC: &str = "test/light";
+const AVAILABILITY: &str = "test/light/availability";
+const WAIT: Duration = Duration::from_secs(15);
+
+fn vars(port: u16) -> Vec<(&'static str, String)> {
+    vec![
+        ("MQTT_IP", "127.0.0.1".to_string()),
+        ("MQTT_PORT", port.to_string()),
+        ("MQTT_USERNAME", "user".to_string()),
+        ("MQTT_PASSWORD", "password".to_string()),
+        ("LIGHT_MQTT_CLIENT_ID", "light".to_string()),
+        ("LIGHT_MQTT_TOPIC", TOPIC.to_string()),
+        ("LIGHT_PIN", "27".to_string()),
+        ("LIGHT_GPIO_BACKEND", "simulated".to_string()),
+        ("LIGHT_SIMULATION_SCRIPT", "on:0.5,off:0.5".to_string()),
+        ("LIGHT_DISCOVERY_PREFIX", "homeassistant".to_string()),
+    ]
+}
+
+fn light(port: u16) -> Service {
+    Service::start(env!("CARGO_BIN_EXE_light"), &vars(port))
+}
+
+#[tokio::test]
+async fn publishes_changes() {
+    let broker = Broker::start();
+    let mut subscriber = Subscriber::connect(broker.port, TOPIC).await;
+    let _service = light(broker.port);
+
+    let mut states = Vec::new();
+    while states.len() < 3 {
+        let publish = subscriber.next(WAIT).await.unwrap();
+        assert!(!publish.retain);
+        states.push(serde_json::from_slice::<Value>(&publish.payload).unwrap()["light"].clone());
+    }
+    assert_ne!(states[0], states[1]);
+    assert_ne!(states[1], states[2]);
+}
+
+#[tokio::test]
+async fn discovery() {
+    let broker = Broker::start();
+    let mut service = light(broker.port);
+    {
+        let mut subscriber = Subscriber::connect(broker.port, TOPIC).await;
+        subscriber.next(WAIT).await.unwrap();
+    }
+
+    // Subscribing afterwards, the config and the availability are retained.
+    let mut subscriber = Subscriber::connect(broker.port, "homeassistant/#").await;
+    let config = subscriber.next(WAIT).await.unwrap();
+    assert_eq!(
+        &config.topic[..],
+        b"homeassistant/binary_sensor/light-rust/config"
+    );
+    assert!(config.retain);
+    let config = serde_json::from_slice::<Value>(&config.payload).unwrap();
+    assert_eq!(config["state_topic"], TOPIC);
+    assert_eq!(config["availability_topic"], json!(AVAILABILITY));
+
+    let mut subscriber = Subscriber::connect(broker.port, AVAILABILITY).await;
+    assert_eq!(&subscriber.next(WAIT).await.unwrap().payload[..], b"online");
+    service.kill();
+    assert_eq!(
+        &subscriber.next(WAIT).await.unwrap().payload[..],
+        b"offline"
+    );
+}
+
+#[test]
+fn meter_not_simulated() {
+    let mut vars = vars(1883);
+    vars.push(("LIGHT_MODE", "bh1750".to_string()));
+    let output = run(env!("CARGO_BIN_EXE_light"), &[], &vars);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("LIGHT_GPIO_BACKEND=simulated only simulates the digital input, not the bh1750"));
+}
@@ -0,0 +1,12 @@
+//! `decode` and its diagnostics must reject any capture without panicking.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpi_gpio::dht22::{analyze, decode, decode_with, DecodeOptions, Pulses};
+
+fuzz_target!(|input: (Pulses, u8)| {
+    let (pulses, len) = input;
+    let _ = decode(&pulses);
+    let _ = decode_with(&pulses, DecodeOptions::ALL);
+    let _ = analyze(&pulses[..usize::from(len) % (pulses.len() + 1)]);
+});
@@ -0,0 +1,378 @@
+//! Light level measured by an LDR or a photodiode through an analog-to-digital converter: an
+//! MCP3008 on SPI or an ADS1115 on I2C.
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    thread::sleep,
+    time::Duration,
+};
+
+use crate::{
+    bus::{I2cBus, SpiBus},
+    dht22::run_blocking,
+    sensor::{BoxFuture, Measurement, Measurements, Quantity, Sensor, SensorError},
+    ReadingError,
+};
+
+/// An analog-to-digital converter reading a single channel.
+pub trait Adc {
+    /// The voltage of the channel, as a fraction of the full scale from 0 to 1.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the converter can't be read.
+    fn read_fraction(&mut self) -> Result<f32, ReadingError>;
+}
+
+/// 10-bit, 8-channel SPI converter.
+#[derive(Debug)]
+pub struct Mcp3008<B> {
+    bus: B,
+    channel: u8,
+}
+
+impl<B: SpiBus> Mcp3008<B> {
+    const MAX: u16 = 0x3ff;
+
+    /// The single-ended `channel`, from 0 to 7, of the converter on `bus`.
+    pub const fn new(bus: B, channel: u8) -> Self {
+        Self {
+            bus,
+            channel: channel & 0x07,
+        }
+    }
+
+    /// The raw 10-bit value of the channel.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the transfer fails.
+    pub fn read_raw(&mut self) -> Result<u16, ReadingError> {
+        // Start bit, then single-ended mode and the channel, then room for the answer.
+        let mut answer = [0; 3];
+        self.bus
+            .transfer(&[0x01, 0x80 | (self.channel << 4), 0x00], &mut answer)?;
+        Ok((u16::from(answer[1] & 0x03) << 8) | u16::from(answer[2]))
+    }
+}
+
+impl<B: SpiBus> Adc for Mcp3008<B> {
+    fn read_fraction(&mut self) -> Result<f32, ReadingError> {
+        Ok(f32::from(self.read_raw()?) / f32::from(Self::MAX))
+    }
+}
+
+/// 16-bit, 4-channel I2C converter.
+#[derive(Debug)]
+pub struct Ads1115<B> {
+    bus: B,
+    address: u8,
+    channel: u8,
+    /// Voltage at the top of the divider, read as the full scale.
+    reference: f32,
+}
+
+impl<B: I2cBus> Ads1115<B> {
+    /// Address with the ADDR pin tied to the ground.
+    pub const DEFAULT_ADDRESS: u8 = 0x48;
+
+    /// Voltage of the full range of the converter.
+    pub const RANGE: f32 = 4.096;
+
+    const CONVERSION: u8 = 0x00;
+    const CONFIG: u8 = 0x01;
+    /// Starts a conversion when written, set when read once the conversion is done.
+    const OS: u16 = 0x8000;
+    /// Single-shot conversion at 128 samples per second with a ±4.096 V range, which covers a
+    /// divider powered by 3.3 V, and the comparator disabled.
+    const SINGLE_SHOT: u16 = 0x0100 | 0x0200 | 0x0080 | 0x0003;
+    const POLLS: usize = 20;
+
+    /// The `channel`, from 0 to 3, against the ground, of the converter at `address`, reading
+    /// a divider powered by `reference` volts, 3.3 from the Raspberry Pi.
+    pub const fn new(bus: B, address: u8, channel: u8, reference: f32) -> Self {
+        Self {
+            bus,
+            address,
+            channel: channel & 0x03,
+            reference,
+        }
+    }
+
+    /// The raw signed value of the channel, positive against the ground.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the converter doesn't answer or the conversion doesn't
+    /// complete.
+    pub fn read_raw(&mut self) -> Result<i16, ReadingError> {
+        let mux = u16::from(0x04 | self.channel) << 12;
+        let [high, low] = (Self::OS | mux | Self::SINGLE_SHOT).to_be_bytes();
+        self.bus.write(self.address, &[Self::CONFIG, high, low])?;
+
+        let mut config = [0; 2];
+        for _ in 0..Self::POLLS {
+            self.bus
+                .write_read(self.address, &[Self::CONFIG], &mut config)?;
+            if u16::from_be_bytes(config) & Self::OS != 0 {
+                let mut conversion = [0; 2];
+                self.bus
+                    .write_read(self.address, &[Self::CONVERSION], &mut conversion)?;
+                return Ok(i16::from_be_bytes(conversion));
+            }
+            sleep(Duration::from_millis(1));
+        }
+        Err(ReadingError::Io(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "ADS1115 conversion didn't complete",
+        )))
+    }
+}
+
+impl<B: I2cBus> Adc for Ads1115<B> {
+    /// The fraction of the reference voltage rather than of the range of the converter.
+    fn read_fraction(&mut self) -> Result<f32, ReadingError> {
+        let volts = f32::from(self.read_raw()?.max(0)) / f32::from(i16::MAX) * Self::RANGE;
+        Ok(volts / self.reference)
+    }
+}
+
+/// Unit of the published light level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unit {
+    #[default]
+    Percent,
+    Lux,
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "percent" => Ok(Self::Percent),
+            "lux" => Ok(Self::Lux),
+            _ => Err(format!("unknown unit {s}, expected percent or lux")),
+        }
+    }
+}
+
+impl Unit {
+    /// Key of the level in the JSON payload.
+    #[must_use]
+    pub const fn key(self) -> &'static str {
+        match self {
+            Self::Percent => "light_percent",
+            Self::Lux => "lux",
+        }
+    }
+}
+
+/// How the converter fraction translates to a light level, and the level to light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub unit: Unit,
+
+    /// Level at the full scale of the converter, 100 for a percentage.
+    pub full_scale: f32,
+
+    /// Whether the voltage drops when the light increases, depending on the side of the divider
+    /// the LDR is on.
+    pub inverted: bool,
+
+    /// Level above which there's light.
+    pub on: f32,
+
+    /// Level below which there's no light anymore, lower than `on` to avoid chattering.
+    pub off: f32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            unit: Unit::Percent,
+            full_scale: 100.0,
+            inverted: false,
+            on: 50.0,
+            off: 47.5,
+        }
+    }
+}
+
+impl Calibration {
+    /// The level of the converter `fraction`.
+    #[must_use]
+    pub fn level(&self, fraction: f32) -> f32 {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let fraction = if self.inverted {
+            1.0 - fraction
+        } else {
+            fraction
+        };
+        fraction * self.full_scale
+    }
+
+    /// Whether there's light at `level`, knowing whether there was light before.
+    #[must_use]
+    pub fn light(&self, level: f32, previous: Option<bool>) -> bool {
+        if previous == Some(true) {
+            level >= self.off
+        } else {
+            level >= self.on
+        }
+    }
+}
+
+/// A reading of a [`LightMeter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightLevel {
+    pub level: f32,
+    pub light: bool,
+}
+
+/// A calibrated light sensor behind a converter.
+pub struct LightMeter {
+    /// Shared with the blocking pool during an asynchronous read.
+    adc: Arc<Mutex<Box<dyn Adc + Send>>>,
+    calibration: Calibration,
+    light: Option<bool>,
+}
+
+impl LightMeter {
+    #[must_use]
+    pub fn new(adc: Box<dyn Adc + Send>, calibration: Calibration) -> Self {
+        Self {
+            adc: Arc::new(Mutex::new(adc)),
+            calibration,
+            light: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// # Errors
+    /// Returns `ReadingError::Io` if the converter can't be read.
+    pub fn read(&mut self) -> Result<LightLevel, ReadingError> {
+        let fraction = read_fraction(&self.adc)?;
+        Ok(self.calibrate(fraction))
+    }
+
+    fn calibrate(&mut self, fraction: f32) -> LightLevel {
+        let level = self.calibration.level(fraction);
+        let light = self.calibration.light(level, self.light);
+        self.light = Some(light);
+        LightLevel { level, light }
+    }
+}
+
+fn read_fraction(adc: &Mutex<Box<dyn Adc + Send>>) -> Result<f32, ReadingError> {
+    adc.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .read_fraction()
+}
+
+impl Sensor for LightMeter {
+    fn name(&self) -> &'static str {
+        "analog"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        match self.calibration.unit {
+            Unit::Percent => &[Quantity::LightLevel, Quantity::Light],
+            Unit::Lux => &[Quantity::Illuminance, Quantity::Light],
+        }
+    }
+
+    /// Read the converter on the blocking pool, the ADS1115 polling for the end of its
+    /// conversion for up to 20 ms.
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        Box::pin(async move {
+            let adc = Arc::clone(&self.adc);
+            let fraction = run_blocking(move || read_fraction(&adc)).await?;
+            let reading = self.calibrate(fraction);
+            let level = match self.calibration.unit {
+                Unit::Percent => Measurement::LightLevel(reading.level),
+                Unit::Lux => Measurement::Illuminance(reading.level),
+            };
+            Ok([level, Measurement::Light(reading.light)]
+                .into_iter()
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adc, Ads1115, Calibration, LightMeter, Mcp3008, Unit};
+    use crate::{
+        bus::mock::MockBus,
+        sensor::{Measurement, Quantity, Sensor},
+    };
+
+    #[test]
+    fn mcp3008() {
+        let mut adc = Mcp3008::new(MockBus::new(&[&[0x00, 0x02, 0x00]]), 3);
+        assert_eq!(adc.read_raw().unwrap(), 512);
+        assert_eq!(adc.bus.writes, [[0x01, 0xb0, 0x00]]);
+    }
+
+    #[test]
+    fn ads1115() {
+        let busy = [0x05, 0x83];
+        let done = [0x85, 0x83];
+        let bus = MockBus::new(&[&busy, &done, &[0x40, 0x00]]);
+        let mut adc = Ads1115::new(bus, 0x49, 1, 3.3);
+        assert_eq!(adc.read_raw().unwrap(), 0x4000);
+        assert_eq!(adc.bus.addresses, [0x49; 4]);
+        assert_eq!(
+            adc.bus.writes,
+            [vec![0x01, 0xd3, 0x83], vec![0x01], vec![0x01], vec![0x00]]
+        );
+
+        // A negative value, below the ground, is clamped.
+        let mut adc = Ads1115::new(MockBus::new(&[&done, &[0xff, 0xf0]]), 0x48, 0, 3.3);
+        assert!(adc.read_fraction().unwrap().abs() < f32::EPSILON);
+
+        // 3.3 V, the top of a divider powered by the Raspberry Pi, is the full scale.
+        let mut adc = Ads1115::new(MockBus::new(&[&done, &[0x67, 0x1f]]), 0x48, 0, 3.3);
+        assert!((adc.read_fraction().unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn hysteresis() {
+        let calibration = Calibration {
+            unit: Unit::Lux,
+            full_scale: 1000.0,
+            inverted: true,
+            on: 300.0,
+            off: 200.0,
+        };
+        assert!((calibration.level(0.75) - 250.0).abs() < 1e-3);
+
+        // The LDR voltage drops when it gets lighter.
+        let fractions = [0.9, 0.6, 0.75, 0.85, 0.75];
+        let mut bus = MockBus::new(&[]);
+        for fraction in fractions {
+            let raw = (fraction * 1023.0_f32).round() as u16;
+            let [high, low] = raw.to_be_bytes();
+            bus.responses.push_back(vec![0x00, high, low]);
+        }
+        let mut meter = LightMeter::new(Box::new(Mcp3008::new(bus, 0)), calibration);
+        let lights = fractions.map(|_| meter.read().unwrap().light);
+        assert_eq!(lights, [false, true, true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn sensor() {
+        let bus = MockBus::new(&[&[0x00, 0x03, 0xff]]);
+        let mut meter = LightMeter::new(Box::new(Mcp3008::new(bus, 0)), Calibration::default());
+        assert_eq!(meter.quantities(), [Quantity::LightLevel, Quantity::Light]);
+        let measurements = Sensor::read(&mut meter).await.unwrap();
+        assert_eq!(measurements.value(Quantity::LightLevel), Some(100.0));
+        assert_eq!(
+            measurements.get(Quantity::Light),
+            Some(Measurement::Light(true))
+        );
+    }
+}
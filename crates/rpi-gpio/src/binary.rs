@@ -0,0 +1,125 @@
+//! Description of a binary sensor, such as a light sensor, a door reed switch, a PIR motion
+//! sensor or a water leak probe: what its state is called and how it's published.
+use serde_json::{json, Value};
+
+/// How the state of a binary input is published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinarySensor {
+    /// Human readable name, used by Home Assistant.
+    pub name: String,
+
+    /// Key of the state in the JSON payload.
+    pub key: String,
+
+    /// Value of the key when the input is active.
+    pub on: Value,
+
+    /// Value of the key when the input is inactive.
+    pub off: Value,
+
+    /// Home Assistant device class, such as `door`, `motion` or `moisture`.
+    pub device_class: Option<String>,
+}
+
+impl Default for BinarySensor {
+    /// A light sensor publishing `{"light": true}`.
+    fn default() -> Self {
+        Self {
+            name: "light".to_string(),
+            key: "light".to_string(),
+            on: Value::Bool(true),
+            off: Value::Bool(false),
+            device_class: Some("light".to_string()),
+        }
+    }
+}
+
+/// Parse a configured payload value: JSON if it is, such as `true` or `1`, a string otherwise.
+#[must_use]
+pub fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// How Home Assistant renders `value` in a template.
+fn template_value(value: &Value) -> String {
+    match value {
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+impl BinarySensor {
+    /// The value of `state`.
+    #[must_use]
+    pub const fn value(&self, state: bool) -> &Value {
+        if state {
+            &self.on
+        } else {
+            &self.off
+        }
+    }
+
+    /// The JSON payload of `state`.
+    #[must_use]
+    pub fn payload(&self, state: bool) -> Value {
+        json!({ &self.key: self.value(state) })
+    }
+
+    /// Topic and payload of the Home Assistant MQTT discovery message of the sensor, which
+    /// should be retained.
+    #[must_use]
+    pub fn discovery(&self, prefix: &str, unique_id: &str, state_topic: &str) -> (String, Value) {
+        let topic = format!("{prefix}/binary_sensor/{unique_id}/config");
+        let mut config = json!({
+            "name": self.name,
+            "unique_id": unique_id,
+            "state_topic": state_topic,
+            "value_template": format!("{{{{ value_json.{} }}}}", self.key),
+            "payload_on": template_value(&self.on),
+            "payload_off": template_value(&self.off),
+        });
+        if let Some(device_class) = &self.device_class {
+            config["device_class"] = json!(device_class);
+        }
+        (topic, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_value, BinarySensor};
+
+    use serde_json::json;
+
+    #[test]
+    fn door() {
+        let door = BinarySensor {
+            name: "Front door".to_string(),
+            key: "door".to_string(),
+            on: parse_value("open"),
+            off: parse_value("closed"),
+            device_class: Some("door".to_string()),
+        };
+        assert_eq!(door.payload(true), json!({ "door": "open" }));
+        assert_eq!(door.payload(false), json!({ "door": "closed" }));
+
+        let (topic, config) = door.discovery("homeassistant", "door-rust", "home/door");
+        assert_eq!(topic, "homeassistant/binary_sensor/door-rust/config");
+        assert_eq!(config["value_template"], "{{ value_json.door }}");
+        assert_eq!(config["payload_on"], "open");
+        assert_eq!(config["device_class"], "door");
+    }
+
+    #[test]
+    fn light() {
+        let light = BinarySensor::default();
+        assert_eq!(light.payload(true), json!({ "light": true }));
+        assert_eq!(parse_value("1"), json!(1));
+
+        let (_, config) = light.discovery("homeassistant", "light-rust", "home/light");
+        assert_eq!(config["payload_on"], "True");
+        assert_eq!(config["payload_off"], "False");
+    }
+}
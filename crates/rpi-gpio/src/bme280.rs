@@ -0,0 +1,315 @@
+//! Bosch BME280 temperature, humidity and pressure sensor on I2C.
+use rppal::i2c::I2c;
+
+use std::{io, thread::sleep, time::Duration};
+
+use crate::{
+    bus::I2cBus,
+    dht22::Reading,
+    sensor::{BlockingSensor, Measurements, Quantity},
+    ReadingError,
+};
+
+const CHIP_ID: u8 = 0xd0;
+const BME280_ID: u8 = 0x60;
+/// First block of compensation parameters, from `dig_T1` to `dig_H1`.
+const CALIBRATION_TP: u8 = 0x88;
+/// Second block of compensation parameters, from `dig_H2` to `dig_H6`.
+const CALIBRATION_H: u8 = 0xe1;
+const CTRL_HUM: u8 = 0xf2;
+const STATUS: u8 = 0xf3;
+const CTRL_MEAS: u8 = 0xf4;
+const DATA: u8 = 0xf7;
+/// Set in the status register while a conversion is running.
+const MEASURING: u8 = 0x08;
+/// Oversampling ×1 of the humidity.
+const HUMIDITY_X1: u8 = 0x01;
+/// Oversampling ×1 of the temperature and the pressure, in forced mode: a single conversion,
+/// then sleep.
+const FORCED_X1: u8 = 0x25;
+/// Longest conversion with every oversampling at ×1 is 9.3 ms.
+const CONVERSION_TIME: Duration = Duration::from_millis(10);
+/// Polls of the status once the conversion should be done.
+const POLLS: usize = 5;
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Compensation parameters trimmed in the factory, named after the datasheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p: [i16; 8],
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+// The compensations follow the floating-point formulas of the datasheet.
+#[allow(clippy::suboptimal_flops)]
+impl Calibration {
+    fn parse(tp: &[u8; 26], h: [u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]);
+        // dig_H4 and dig_H5 are 12-bit and share the nibbles of 0xe5.
+        let h4 = (i16::from(i8::from_le_bytes([h[3]])) << 4) | i16::from(h[4] & 0x0f);
+        let h5 = (i16::from(i8::from_le_bytes([h[5]])) << 4) | i16::from(h[4] >> 4);
+        Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p: [8, 10, 12, 14, 16, 18, 20, 22].map(i16_at),
+            h1: tp[25],
+            h2: i16::from_le_bytes([h[0], h[1]]),
+            h3: h[2],
+            h4,
+            h5,
+            h6: i8::from_le_bytes([h[6]]),
+        }
+    }
+
+    /// Temperature in °C, and the fine temperature the other compensations depend on.
+    fn temperature(&self, adc: i32) -> (f64, f64) {
+        let adc = f64::from(adc);
+        let t1 = f64::from(self.t1);
+        let var1 = (adc / 16384.0 - t1 / 1024.0) * f64::from(self.t2);
+        let var2 = (adc / 131_072.0 - t1 / 8192.0).powi(2) * f64::from(self.t3);
+        let fine = var1 + var2;
+        (fine / 5120.0, fine)
+    }
+
+    /// Pressure in Pa.
+    fn pressure(&self, adc: i32, fine: f64) -> f64 {
+        let [p2, p3, p4, p5, p6, p7, p8, p9] = self.p.map(f64::from);
+        let mut var1 = fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p6 / 32768.0;
+        var2 += var1 * p5 * 2.0;
+        var2 = var2 / 4.0 + p4 * 65536.0;
+        var1 = (p3 * var1 * var1 / 524_288.0 + p2 * var1) / 524_288.0;
+        var1 = (1.0 + var1 / 32768.0) * f64::from(self.p1);
+        if var1 == 0.0 {
+            return 0.0;
+        }
+        let pressure = (1_048_576.0 - f64::from(adc) - var2 / 4096.0) * 6250.0 / var1;
+        let var1 = p9 * pressure * pressure / 2_147_483_648.0;
+        let var2 = pressure * p8 / 32768.0;
+        pressure + (var1 + var2 + p7) / 16.0
+    }
+
+    /// Relative humidity in %.
+    fn humidity(&self, adc: i32, fine: f64) -> f64 {
+        let var = fine - 76800.0;
+        let var = (f64::from(adc)
+            - (f64::from(self.h4) * 64.0 + f64::from(self.h5) / 16384.0 * var))
+            * (f64::from(self.h2) / 65536.0
+                * (1.0
+                    + f64::from(self.h6) / 67_108_864.0
+                        * var
+                        * (1.0 + f64::from(self.h3) / 67_108_864.0 * var)));
+        let var = var * (1.0 - f64::from(self.h1) * var / 524_288.0);
+        var.clamp(0.0, 100.0)
+    }
+}
+
+/// A BME280, with its compensation parameters.
+#[derive(Debug)]
+pub struct Bme280<B> {
+    bus: B,
+    address: u8,
+    calibration: Calibration,
+}
+
+impl<B: I2cBus> Bme280<B> {
+    /// Address with SDO tied to the ground, 0x77 when it's tied to VDDIO.
+    pub const DEFAULT_ADDRESS: u8 = 0x76;
+
+    /// Check the chip at `address` and read its compensation parameters.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the chip doesn't answer or isn't a BME280, a BMP280
+    /// having no humidity sensor.
+    pub fn new(mut bus: B, address: u8) -> Result<Self, ReadingError> {
+        let mut id = [0; 1];
+        bus.write_read(address, &[CHIP_ID], &mut id)?;
+        if id[0] != BME280_ID {
+            return Err(ReadingError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chip {:#04x} at {address:#04x} isn't a BME280", id[0]),
+            )));
+        }
+
+        let mut tp = [0; 26];
+        bus.write_read(address, &[CALIBRATION_TP], &mut tp)?;
+        let mut h = [0; 7];
+        bus.write_read(address, &[CALIBRATION_H], &mut h)?;
+        Ok(Self {
+            bus,
+            address,
+            calibration: Calibration::parse(&tp, h),
+        })
+    }
+
+    /// Run a conversion and read it, blocking for about 10 ms.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the chip doesn't answer or the conversion doesn't complete.
+    pub fn read(&mut self) -> Result<Reading, ReadingError> {
+        self.start()?;
+        sleep(CONVERSION_TIME);
+        self.fetch()
+    }
+
+    /// Start a conversion, done after about 10 ms.
+    fn start(&mut self) -> Result<(), ReadingError> {
+        // The humidity settings only apply once the measurement control is written.
+        self.bus.write(self.address, &[CTRL_HUM, HUMIDITY_X1])?;
+        self.bus.write(self.address, &[CTRL_MEAS, FORCED_X1])
+    }
+
+    /// Wait for the conversion to complete and read it.
+    #[allow(clippy::cast_possible_truncation)]
+    fn fetch(&mut self) -> Result<Reading, ReadingError> {
+        self.wait()?;
+
+        let mut data = [0; 8];
+        self.bus.write_read(self.address, &[DATA], &mut data)?;
+        let adc20 = |i: usize| {
+            (i32::from(data[i]) << 12) | (i32::from(data[i + 1]) << 4) | i32::from(data[i + 2] >> 4)
+        };
+        let (temperature, fine) = self.calibration.temperature(adc20(3));
+        let pressure = self.calibration.pressure(adc20(0), fine);
+        let humidity = self
+            .calibration
+            .humidity(i32::from(u16::from_be_bytes([data[6], data[7]])), fine);
+
+        Ok(Reading {
+            temperature: temperature as f32,
+            humidity: humidity as f32,
+            pressure: Some((pressure / 100.0) as f32),
+        })
+    }
+
+    fn wait(&mut self) -> Result<(), ReadingError> {
+        let mut status = [0; 1];
+        for _ in 0..POLLS {
+            self.bus.write_read(self.address, &[STATUS], &mut status)?;
+            if status[0] & MEASURING == 0 {
+                return Ok(());
+            }
+            sleep(POLL_INTERVAL);
+        }
+        Err(ReadingError::Io(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "BME280 conversion didn't complete",
+        )))
+    }
+}
+
+impl<B: I2cBus + Send + 'static> BlockingSensor for Bme280<B> {
+    fn name(&self) -> &'static str {
+        "BME280"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[
+            Quantity::Temperature,
+            Quantity::Humidity,
+            Quantity::Pressure,
+        ]
+    }
+
+    fn read_blocking(&mut self) -> Result<Measurements, ReadingError> {
+        Ok(self.read()?.into())
+    }
+}
+
+/// Read the BME280 at `address` on the I2C `bus`, on the blocking pool.
+///
+/// # Errors
+/// See [`Bme280::new`] and [`Bme280::read`].
+pub async fn read_async(bus: u8, address: u8) -> Result<Reading, ReadingError> {
+    crate::dht22::run_blocking(move || Bme280::new(I2c::with_bus(bus)?, address)?.read()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bme280, Calibration};
+    use crate::{
+        bus::mock::MockBus,
+        sensor::{Blocking, Quantity, Sensor},
+    };
+
+    /// Parameters of the example of the BMP280 datasheet, which shares the temperature and
+    /// pressure compensation, and typical humidity parameters.
+    fn calibration() -> ([u8; 26], [u8; 7]) {
+        let mut tp = Vec::new();
+        tp.extend(27504_u16.to_le_bytes());
+        for value in [26435_i16, -1000] {
+            tp.extend(value.to_le_bytes());
+        }
+        tp.extend(36477_u16.to_le_bytes());
+        for value in [-10685_i16, 3024, 2855, 140, -7, 15500, -14600, 6000] {
+            tp.extend(value.to_le_bytes());
+        }
+        tp.extend([0x00, 75]);
+        // dig_H2 = 362, dig_H3 = 0, dig_H4 = 313, dig_H5 = 50, dig_H6 = 30.
+        let h = [0x6a, 0x01, 0x00, 0x13, 0x29, 0x03, 0x1e];
+        (tp.try_into().unwrap(), h)
+    }
+
+    #[test]
+    fn parameters() {
+        let (tp, h) = calibration();
+        let calibration = Calibration::parse(&tp, h);
+        assert_eq!(calibration.t3, -1000);
+        assert_eq!(calibration.p[6], -14600);
+        assert_eq!(calibration.h1, 75);
+        assert_eq!((calibration.h4, calibration.h5), (313, 50));
+
+        let negative = Calibration::parse(&tp, [0, 0, 0, 0xff, 0xf7, 0xff, 0]);
+        assert_eq!((negative.h4, negative.h5), (-9, -1));
+    }
+
+    #[test]
+    fn read() {
+        let (tp, h) = calibration();
+        let data = [0x65, 0x5a, 0xc0, 0x7e, 0xed, 0x00, 0x75, 0x30];
+        let bus = MockBus::new(&[&[0x60], &tp, &h, &[0x08], &[0x00], &data]);
+        let mut sensor = Bme280::new(bus, 0x76).unwrap();
+        let reading = sensor.read().unwrap();
+        assert!((reading.temperature - 25.08).abs() < 0.01);
+        assert!((reading.pressure.unwrap() - 1006.53).abs() < 0.01);
+        assert!((reading.humidity - 55.0).abs() < 0.01);
+        assert_eq!(
+            &sensor.bus.writes[3..],
+            [
+                vec![0xf2, 0x01],
+                vec![0xf4, 0x25],
+                vec![0xf3],
+                vec![0xf3],
+                vec![0xf7]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sensor() {
+        let (tp, h) = calibration();
+        let data = [0x65, 0x5a, 0xc0, 0x7e, 0xed, 0x00, 0x75, 0x30];
+        let bus = MockBus::new(&[&[0x60], &tp, &h, &[0x00], &data]);
+        let mut sensor = Blocking::new(Bme280::new(bus, 0x76).unwrap());
+        let measurements = sensor.read().await.unwrap();
+        assert!((measurements.value(Quantity::Pressure).unwrap() - 1006.53).abs() < 0.01);
+    }
+
+    #[test]
+    fn wrong_chip() {
+        // A BMP280.
+        assert!(Bme280::new(MockBus::new(&[&[0x58]]), 0x76).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Minimal SPI and I2C bus interfaces used by the drivers, implemented for `rppal` and mocked in
+//! the tests.
+use rppal::{i2c::I2c, spi::Spi};
+
+use std::io;
+
+use crate::ReadingError;
+
+impl From<rppal::spi::Error> for ReadingError {
+    fn from(err: rppal::spi::Error) -> Self {
+        Self::Io(io::Error::other(err))
+    }
+}
+
+impl From<rppal::i2c::Error> for ReadingError {
+    fn from(err: rppal::i2c::Error) -> Self {
+        Self::Io(io::Error::other(err))
+    }
+}
+
+/// A full-duplex SPI bus with the chip select of a single device.
+pub trait SpiBus {
+    /// Write `write` while reading as many bytes into `read`.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the transfer fails.
+    fn transfer(&mut self, write: &[u8], read: &mut [u8]) -> Result<(), ReadingError>;
+}
+
+/// An I2C bus.
+pub trait I2cBus {
+    /// Write `bytes` to the device at `address`.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the device doesn't acknowledge.
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ReadingError>;
+
+    /// Fill `read` with bytes from the device at `address`, for devices without registers.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the device doesn't acknowledge.
+    fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), ReadingError>;
+
+    /// Write `write` to the device at `address`, then fill `read` with its answer.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the device doesn't acknowledge.
+    fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), ReadingError>;
+}
+
+impl SpiBus for Spi {
+    fn transfer(&mut self, write: &[u8], read: &mut [u8]) -> Result<(), ReadingError> {
+        Self::transfer(self, read, write)?;
+        Ok(())
+    }
+}
+
+impl I2cBus for I2c {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ReadingError> {
+        self.set_slave_address(u16::from(address))?;
+        Self::write(self, bytes)?;
+        Ok(())
+    }
+
+    fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), ReadingError> {
+        self.set_slave_address(u16::from(address))?;
+        Self::read(self, read)?;
+        Ok(())
+    }
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), ReadingError> {
+        self.set_slave_address(u16::from(address))?;
+        Self::write_read(self, write, read)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::{I2cBus, SpiBus};
+    use crate::ReadingError;
+
+    use std::collections::VecDeque;
+
+    /// Records the writes and answers with the queued responses, in order.
+    #[derive(Debug, Default)]
+    pub struct MockBus {
+        pub writes: Vec<Vec<u8>>,
+        pub addresses: Vec<u8>,
+        pub responses: VecDeque<Vec<u8>>,
+    }
+
+    impl MockBus {
+        pub fn new(responses: &[&[u8]]) -> Self {
+            Self {
+                responses: responses.iter().map(|response| response.to_vec()).collect(),
+                ..Self::default()
+            }
+        }
+
+        fn respond(&mut self, read: &mut [u8]) {
+            let response = self.responses.pop_front().expect("unexpected read");
+            read.copy_from_slice(&response);
+        }
+    }
+
+    impl SpiBus for MockBus {
+        fn transfer(&mut self, write: &[u8], read: &mut [u8]) -> Result<(), ReadingError> {
+            self.writes.push(write.to_vec());
+            self.respond(read);
+            Ok(())
+        }
+    }
+
+    impl I2cBus for MockBus {
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ReadingError> {
+            self.addresses.push(address);
+            self.writes.push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), ReadingError> {
+            self.addresses.push(address);
+            self.respond(read);
+            Ok(())
+        }
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            write: &[u8],
+            read: &mut [u8],
+        ) -> Result<(), ReadingError> {
+            self.write(address, write)?;
+            self.respond(read);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Calibration of the measurements against a reference instrument.
+//!
+//! A DHT22 commonly reads a degree or a few percent of humidity off. Each sensor may get a
+//! correction per quantity: a table of raw values and the values of the reference at the same
+//! time, interpolated linearly, then a slope and an offset. A sensor is identified by its pin,
+//! by `bus:address` on I2C such as `1:0x76`, by its IIO device such as `iio:device0`, or by the
+//! ROM id of a 1-Wire probe. The calibrations are loaded from a JSON file:
+//!
+//! ```json
+//! {
+//!     "4": {
+//!         "temperature": { "offset": -0.8 },
+//!         "humidity": { "slope": 0.97, "offset": 2.1 }
+//!     },
+//!     "1:0x76": {
+//!         "humidity": { "offset": -1.5 }
+//!     },
+//!     "28-0316a2797fff": {
+//!         "temperature": { "table": [[0.0, 0.4], [25.0, 25.0], [60.0, 59.1]] }
+//!     }
+//! }
+//! ```
+use std::{collections::HashMap, fs};
+
+use serde_json::Value;
+
+use crate::sensor::{Measurements, Quantity};
+
+/// Correction of the values of a quantity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Correction {
+    /// Added last.
+    pub offset: f32,
+
+    /// Multiplies the value given by the table.
+    pub slope: f32,
+
+    /// Raw values and the reference values measured at the same time, by increasing raw value.
+    pub table: Vec<(f32, f32)>,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            slope: 1.0,
+            table: Vec::new(),
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn number(value: &Value, name: &str) -> Result<f32, String> {
+    value
+        .as_f64()
+        .map(|number| number as f32)
+        .filter(|number| number.is_finite())
+        .ok_or_else(|| format!("{name} is not a number: {value}"))
+}
+
+impl Correction {
+    /// The calibrated value of `raw`.
+    #[must_use]
+    pub fn apply(&self, raw: f32) -> f32 {
+        self.slope.mul_add(self.interpolate(raw), self.offset)
+    }
+
+    /// The reference value of `raw` according to the table, extrapolated from the closest
+    /// segment outside of it.
+    fn interpolate(&self, raw: f32) -> f32 {
+        match self.table.as_slice() {
+            [] => raw,
+            [(x, y)] => raw + (y - x),
+            table => {
+                let segment = table
+                    .windows(2)
+                    .find(|segment| raw <= segment[1].0)
+                    .unwrap_or_else(|| &table[table.len() - 2..]);
+                let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+                (y1 - y0).mul_add((raw - x0) / (x1 - x0), y0)
+            }
+        }
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let fields = value
+            .as_object()
+            .ok_or_else(|| format!("expected an object, got {value}"))?;
+        let mut correction = Self::default();
+        for (name, value) in fields {
+            match name.as_str() {
+                "offset" => correction.offset = number(value, name)?,
+                "slope" => correction.slope = number(value, name)?,
+                "table" => {
+                    let points = value
+                        .as_array()
+                        .ok_or_else(|| format!("table is not an array: {value}"))?;
+                    for point in points {
+                        let Some([raw, reference]) = point.as_array().map(Vec::as_slice) else {
+                            return Err(format!("expected a [raw, reference] pair, got {point}"));
+                        };
+                        let point = (number(raw, "raw")?, number(reference, "reference")?);
+                        if correction
+                            .table
+                            .last()
+                            .is_some_and(|last| last.0 >= point.0)
+                        {
+                            return Err("the raw values of the table must increase".to_string());
+                        }
+                        correction.table.push(point);
+                    }
+                }
+                _ => return Err(format!("unknown field {name}")),
+            }
+        }
+        Ok(correction)
+    }
+}
+
+/// Measurements after calibration, and the raw ones for diagnostics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibrated {
+    pub measurements: Measurements,
+
+    /// The measurements before calibration, `None` if the sensor isn't calibrated.
+    pub raw: Option<Measurements>,
+}
+
+impl From<Measurements> for Calibrated {
+    /// Measurements of a sensor without calibration.
+    fn from(measurements: Measurements) -> Self {
+        Self {
+            measurements,
+            raw: None,
+        }
+    }
+}
+
+/// Corrections of the quantities measured by a sensor.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calibration(Vec<(Quantity, Correction)>);
+
+impl Calibration {
+    #[must_use]
+    pub fn correction(&self, quantity: Quantity) -> Option<&Correction> {
+        self.0
+            .iter()
+            .find(|(corrected, _)| *corrected == quantity)
+            .map(|(_, correction)| correction)
+    }
+
+    /// Correct `raw`, keeping the humidity within 0 and 100%.
+    #[must_use]
+    pub fn apply(&self, raw: Measurements) -> Calibrated {
+        let measurements = raw
+            .iter()
+            .map(|measurement| {
+                let quantity = measurement.quantity();
+                match (measurement.number(), self.correction(quantity)) {
+                    (Some(value), Some(correction)) => {
+                        let value = correction.apply(value);
+                        measurement.with_number(if quantity == Quantity::Humidity {
+                            value.clamp(0.0, 100.0)
+                        } else {
+                            value
+                        })
+                    }
+                    _ => measurement,
+                }
+            })
+            .collect();
+        Calibrated {
+            measurements,
+            raw: Some(raw),
+        }
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let corrections = value
+            .as_object()
+            .ok_or_else(|| format!("expected an object, got {value}"))?;
+        corrections
+            .iter()
+            .map(|(key, correction)| {
+                let quantity = key.parse::<Quantity>()?;
+                if quantity == Quantity::Light {
+                    return Err("light is a state, it can't be calibrated".to_string());
+                }
+                let correction =
+                    Correction::from_json(correction).map_err(|e| format!("{key}: {e}"))?;
+                Ok((quantity, correction))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Calibration of each sensor, by pin, I2C bus and address, IIO device or ROM id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calibrations(HashMap<String, Calibration>);
+
+impl Calibrations {
+    /// Load the calibrations from a JSON file, see the [module](self) documentation.
+    ///
+    /// # Errors
+    /// Returns a message if the file can't be read or is invalid.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("can't read {path}: {e}"))?;
+        let value = serde_json::from_str(&contents).map_err(|e| format!("{path}: {e}"))?;
+        Self::from_json(&value).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// # Errors
+    /// Returns a message if a calibration is invalid.
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let sensors = value
+            .as_object()
+            .ok_or_else(|| format!("expected an object, got {value}"))?;
+        sensors
+            .iter()
+            .map(|(sensor, calibration)| {
+                let calibration =
+                    Calibration::from_json(calibration).map_err(|e| format!("{sensor}: {e}"))?;
+                Ok((sensor.clone(), calibration))
+            })
+            .collect::<Result<_, String>>()
+            .map(Self)
+    }
+
+    /// The calibration of `sensor`, identified as in the [module](self) documentation.
+    #[must_use]
+    pub fn get(&self, sensor: &str) -> Option<&Calibration> {
+        self.0.get(sensor)
+    }
+
+    /// Calibrate the `raw` measurements of `sensor`, if it has a calibration.
+    #[must_use]
+    pub fn calibrate(&self, sensor: &str, raw: Measurements) -> Calibrated {
+        match self.get(sensor) {
+            Some(calibration) => calibration.apply(raw),
+            None => raw.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::{Calibrations, Correction};
+    use crate::{
+        dht22::Reading,
+        sensor::{Measurements, Quantity},
+    };
+
+    use serde_json::json;
+
+    #[test]
+    fn correction() {
+        let linear = Correction {
+            offset: 2.0,
+            slope: 0.5,
+            table: Vec::new(),
+        };
+        assert_eq!(linear.apply(40.0), 22.0);
+
+        let table = Correction {
+            table: vec![(0.0, 1.0), (20.0, 20.0), (40.0, 38.0)],
+            ..Correction::default()
+        };
+        assert_eq!(table.apply(10.0), 10.5);
+        assert_eq!(table.apply(30.0), 29.0);
+        // Extrapolated from the closest segment.
+        assert_eq!(table.apply(-10.0), -8.5);
+        assert_eq!(table.apply(50.0), 47.0);
+
+        let point = Correction {
+            table: vec![(20.0, 21.5)],
+            ..Correction::default()
+        };
+        assert_eq!(point.apply(30.0), 31.5);
+    }
+
+    #[test]
+    fn calibrate() {
+        let calibrations = Calibrations::from_json(&json!({
+            "4": {
+                "temperature": { "offset": -0.5 },
+                "humidity": { "slope": 1.1, "offset": 2.0 },
+            },
+        }))
+        .unwrap();
+        let raw = Measurements::from(Reading {
+            temperature: 21.5,
+            humidity: 95.0,
+            pressure: None,
+        });
+
+        let calibrated = calibrations.calibrate("4", raw.clone());
+        assert_eq!(
+            calibrated.measurements.value(Quantity::Temperature),
+            Some(21.0)
+        );
+        assert_eq!(
+            calibrated.measurements.value(Quantity::Humidity),
+            Some(100.0)
+        );
+        assert_eq!(calibrated.raw, Some(raw.clone()));
+
+        let uncalibrated = calibrations.calibrate("17", raw.clone());
+        assert_eq!(uncalibrated.measurements, raw);
+        assert_eq!(uncalibrated.raw, None);
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |value| Calibrations::from_json(&value).unwrap_err();
+        assert_eq!(
+            error(json!({ "4": { "temperature": { "gain": 2 } } })),
+            "4: temperature: unknown field gain"
+        );
+        assert_eq!(
+            error(json!({ "4": { "humidity": { "table": [[50, 52], [40, 41]] } } })),
+            "4: humidity: the raw values of the table must increase"
+        );
+        assert_eq!(
+            error(json!({ "4": { "light": { "offset": 1 } } })),
+            "4: light is a state, it can't be calibrated"
+        );
+        assert_eq!(
+            error(json!({ "4": { "dew_point": {} } })),
+            "4: unknown quantity dew_point"
+        );
+    }
+}
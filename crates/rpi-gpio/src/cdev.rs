@@ -0,0 +1,181 @@
+//! GPIO access through the Linux character device (`/dev/gpiochipN`), for the boards that
+//! `rppal` doesn't support, such as the Orange Pi and Rock boards.
+use gpio_cdev::{
+    chips, Chip, EventRequestFlags, Line, LineEventHandle, LineHandle, LineRequestFlags,
+};
+
+use std::{fmt, io, str::FromStr};
+
+use crate::{Pull, ReadingError};
+
+/// Consumer label of the requested lines, shown by `gpioinfo`.
+const CONSUMER: &str = "rpi-gpio";
+
+const DEFAULT_CHIP: &str = "gpiochip0";
+
+impl From<gpio_cdev::Error> for ReadingError {
+    fn from(err: gpio_cdev::Error) -> Self {
+        Self::Io(io::Error::other(err))
+    }
+}
+
+/// A GPIO line, parsed from `gpiochip1:17`, `17` for an offset of `gpiochip0`, or a line name
+/// such as `GPIO17` or `PA12`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineSpec {
+    Name(String),
+    Offset { chip: String, offset: u32 },
+}
+
+impl LineSpec {
+    /// The line `offset` of `gpiochip0`, which is the BCM numbering on a Raspberry Pi.
+    #[must_use]
+    pub fn offset(offset: u32) -> Self {
+        Self::Offset {
+            chip: DEFAULT_CHIP.to_string(),
+            offset,
+        }
+    }
+
+    /// Look the line up.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the chip can't be opened or there's no such line.
+    pub fn find(&self) -> Result<Line, ReadingError> {
+        match self {
+            Self::Offset { chip, offset } => {
+                Ok(Chip::new(format!("/dev/{chip}"))?.get_line(*offset)?)
+            }
+            Self::Name(name) => {
+                for chip in chips()? {
+                    for line in chip?.lines() {
+                        if line.info()?.name() == Some(name.as_str()) {
+                            return Ok(line);
+                        }
+                    }
+                }
+                Err(ReadingError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no GPIO line named {name}"),
+                )))
+            }
+        }
+    }
+
+    /// Request the line as an input, with the bias of `pull` or the current one if `None` or
+    /// floating.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the line can't be found or is busy.
+    pub fn input(&self, pull: Option<Pull>) -> Result<LineHandle, ReadingError> {
+        request_input(&self.find()?, pull)
+    }
+
+    /// Request the line as an input reporting both edges, see [`LineSpec::input`].
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the line can't be found or is busy.
+    pub fn events(&self, pull: Option<Pull>) -> Result<LineEventHandle, ReadingError> {
+        Ok(self
+            .find()?
+            .events(input_flags(pull), EventRequestFlags::BOTH_EDGES, CONSUMER)?)
+    }
+
+    /// Request the line as an output at `value`.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the line can't be found or is busy.
+    pub fn output(&self, value: u8) -> Result<LineHandle, ReadingError> {
+        request_output(&self.find()?, value)
+    }
+}
+
+/// Request a line already looked up as an input, see [`LineSpec::input`]. It only takes an
+/// ioctl, unlike looking the line up again.
+///
+/// # Errors
+/// Returns `ReadingError::Io` if the line is busy.
+pub fn request_input(line: &Line, pull: Option<Pull>) -> Result<LineHandle, ReadingError> {
+    Ok(line.request(input_flags(pull), 0, CONSUMER)?)
+}
+
+/// Request a line already looked up as an output at `value`, see [`request_input`].
+///
+/// # Errors
+/// Returns `ReadingError::Io` if the line is busy.
+pub fn request_output(line: &Line, value: u8) -> Result<LineHandle, ReadingError> {
+    Ok(line.request(LineRequestFlags::OUTPUT, value, CONSUMER)?)
+}
+
+/// The bias flags, which `gpio-cdev` doesn't define, were added in Linux 5.5. A floating input
+/// sets none, leaving the bias of the line as it is, so that it works on older kernels too.
+fn input_flags(pull: Option<Pull>) -> LineRequestFlags {
+    let bias = match pull {
+        None | Some(Pull::Floating) => 0,
+        Some(Pull::Up) => 1 << 5,
+        Some(Pull::Down) => 1 << 6,
+    };
+    LineRequestFlags::INPUT | LineRequestFlags::from_bits_retain(bias)
+}
+
+impl FromStr for LineSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty line".to_string());
+        }
+        if let Ok(offset) = s.parse() {
+            return Ok(Self::offset(offset));
+        }
+        match s.split_once(':') {
+            Some((chip, offset)) if chip.starts_with("gpiochip") => Ok(Self::Offset {
+                chip: chip.to_string(),
+                offset: offset
+                    .parse()
+                    .map_err(|_| format!("invalid offset {offset}"))?,
+            }),
+            _ => Ok(Self::Name(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for LineSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Offset { chip, offset } => write!(f, "{chip}:{offset}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{input_flags, LineSpec};
+    use crate::Pull;
+
+    use gpio_cdev::LineRequestFlags;
+
+    #[test]
+    fn parse() {
+        assert_eq!("17".parse(), Ok(LineSpec::offset(17)));
+        assert_eq!(
+            "gpiochip1:12".parse(),
+            Ok(LineSpec::Offset {
+                chip: "gpiochip1".to_string(),
+                offset: 12
+            })
+        );
+        assert_eq!("PA12".parse(), Ok(LineSpec::Name("PA12".to_string())));
+        assert!("gpiochip1:twelve".parse::<LineSpec>().is_err());
+        assert_eq!(LineSpec::offset(4).to_string(), "gpiochip0:4");
+    }
+
+    #[test]
+    fn bias() {
+        let input = LineRequestFlags::INPUT.bits();
+        assert_eq!(input_flags(Some(Pull::Floating)).bits(), input);
+        assert_eq!(input_flags(None).bits(), input);
+        assert_eq!(input_flags(Some(Pull::Up)).bits(), input | 1 << 5);
+    }
+}
@@ -9,18 +9,28 @@
 use rppal::gpio::{Gpio, Level, Mode};
 
 use std::{
+    fmt, io, panic,
     ptr::{read_volatile, write_volatile},
+    str::FromStr,
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::ReadingError;
+#[cfg(feature = "cdev")]
+use crate::cdev::{self, LineSpec};
+use crate::{
+    realtime::{self, RealtimeOptions},
+    sensor::{BoxFuture, Measurements, Quantity, Sensor, SensorError},
+    ReadingError, TimeoutPhase,
+};
 
-/// A temperature and humidity reading from the DHT22.
+/// A temperature and humidity reading, from the DHT22 or another sensor.
 #[derive(Debug, Clone, Copy)]
 pub struct Reading {
     pub temperature: f32,
     pub humidity: f32,
+    /// Pressure in hPa, for the sensors measuring it.
+    pub pressure: Option<f32>,
 }
 
 impl From<rppal::gpio::Error> for ReadingError {
@@ -29,31 +39,89 @@ impl From<rppal::gpio::Error> for ReadingError {
     }
 }
 
-const MAX_COUNT: usize = 32000;
+/// Length of a pulse at which a capture times out.
+pub(crate) const MAX_COUNT: usize = 32000;
 const DHT_PULSES: usize = 41;
 
+/// Raw capture of a reading: the length of each low and high period, in busy-loop iterations.
+///
+/// The first pair is the answer of the sensor, the 40 next ones are the data bits.
+pub type Pulses = [usize; DHT_PULSES * 2];
+
+/// Diagnostics of a raw capture.
+#[derive(Debug)]
+pub struct Analysis {
+    /// Length above which a high period is decoded as a 1.
+    pub threshold: usize,
+
+    /// Distance between the high period of each captured data bit and the threshold, negative
+    /// for a 0.
+    pub margins: Vec<i64>,
+
+    /// Outcome of decoding the capture.
+    pub result: Result<Reading, ReadingError>,
+}
+
+impl Analysis {
+    /// The data bit closest to the threshold and its margin.
+    #[must_use]
+    pub fn weakest_bit(&self) -> Option<(usize, i64)> {
+        self.margins
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by_key(|(_, margin)| margin.abs())
+    }
+}
+
 fn tiny_sleep() {
     let mut i = 0;
     unsafe {
-        while read_volatile(&i) < 50 {
-            write_volatile(&mut i, read_volatile(&i) + 1);
+        while read_volatile(&raw const i) < 50 {
+            write_volatile(&raw mut i, read_volatile(&raw const i) + 1);
         }
     }
 }
 
-/// # Errors
-/// Returns a `ReadingError` if there's an error when reading.
-fn decode(arr: &[usize; DHT_PULSES * 2]) -> Result<Reading, ReadingError> {
-    let mut threshold: usize = 0;
+/// Mean of `lengths`, 0 if there are none, without overflowing on corrupted captures.
+fn mean(lengths: impl Iterator<Item = usize>) -> usize {
+    let (sum, count) = lengths.fold((0_u128, 0_u128), |(sum, count), length| {
+        (sum + length as u128, count + 1)
+    });
+    if count == 0 {
+        return 0;
+    }
+    usize::try_from(sum / count).unwrap_or(usize::MAX)
+}
 
-    let mut i = 2;
-    while i < DHT_PULSES * 2 {
-        threshold += arr[i];
+/// Middle of `a` and `b`, rounded down, without overflowing.
+const fn midpoint(a: usize, b: usize) -> usize {
+    a / 2 + b / 2 + (a & b & 1)
+}
 
-        i += 2;
+/// Mean length of the low periods preceding the data bits.
+fn threshold(pulses: &[usize]) -> usize {
+    mean(pulses.iter().skip(2).step_by(2).copied())
+}
+
+/// Step of the protocol at which a capture of `len` pulses stopped.
+const fn timeout_phase(len: usize) -> TimeoutPhase {
+    let pulse = len.saturating_sub(1) / 2;
+    if pulse == 0 {
+        TimeoutPhase::StartHandshake
+    } else if len % 2 == 1 {
+        TimeoutPhase::BitLow(pulse - 1)
+    } else {
+        TimeoutPhase::BitHigh(pulse - 1)
     }
+}
 
-    threshold /= DHT_PULSES - 1;
+/// Decode a raw capture.
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+pub fn decode(arr: &Pulses) -> Result<Reading, ReadingError> {
+    let threshold = threshold(arr);
 
     let mut data = [0_u8; 5];
     let mut i = 3;
@@ -69,15 +137,27 @@ fn decode(arr: &[usize; DHT_PULSES * 2]) -> Result<Reading, ReadingError> {
         i += 2;
     }
 
-    if data[4]
-        != data[0]
-            .wrapping_add(data[1])
-            .wrapping_add(data[2])
-            .wrapping_add(data[3])
-    {
-        return Result::Err(ReadingError::Checksum);
+    let expected = checksum(data);
+    if data[4] != expected {
+        return Result::Err(ReadingError::Checksum {
+            pulses: arr.to_vec(),
+            data,
+            expected,
+            actual: data[4],
+        });
     }
 
+    Result::Ok(to_reading(data))
+}
+
+const fn checksum(data: [u8; 5]) -> u8 {
+    data[0]
+        .wrapping_add(data[1])
+        .wrapping_add(data[2])
+        .wrapping_add(data[3])
+}
+
+fn to_reading(data: [u8; 5]) -> Reading {
     let h_dec = u16::from(data[0]) * 256 + u16::from(data[1]);
     let h = f32::from(h_dec) / 10.0f32;
 
@@ -87,10 +167,268 @@ fn decode(arr: &[usize; DHT_PULSES * 2]) -> Result<Reading, ReadingError> {
         t *= -1.0f32;
     }
 
-    Result::Ok(Reading {
+    Reading {
         temperature: t,
         humidity: h,
-    })
+        pressure: None,
+    }
+}
+
+/// Heuristics [`decode_with`] may use to recover a capture that [`decode`] rejects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Split the high periods in two clusters instead of comparing them to the mean low period.
+    pub clustering: bool,
+
+    /// Shift the capture by one pulse when it has an extra or a missing leading pulse.
+    pub realign: bool,
+
+    /// Flip one of the bits closest to the threshold when the checksum doesn't match.
+    pub bit_flip: bool,
+}
+
+impl DecodeOptions {
+    pub const ALL: Self = Self {
+        clustering: true,
+        realign: true,
+        bit_flip: true,
+    };
+}
+
+impl FromStr for DecodeOptions {
+    type Err = String;
+
+    /// Parse a comma-separated list of heuristics, `all` or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = Self::default();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "all" => options = Self::ALL,
+                "none" => options = Self::default(),
+                "clustering" => options.clustering = true,
+                "realign" => options.realign = true,
+                "bit_flip" => options.bit_flip = true,
+                _ => return Err(format!("unknown decode heuristic {name}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// How a capture was misaligned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misalignment {
+    /// A spurious pulse preceded the answer of the sensor, the last bit was lost.
+    ExtraLeadingPulse,
+
+    /// The answer of the sensor was missed, the capture starts with the first data bit.
+    MissingLeadingPulse,
+}
+
+/// Heuristics that were needed to decode a capture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// The two-cluster threshold was used.
+    pub clustering: bool,
+
+    /// The capture was shifted by one pulse.
+    pub misalignment: Option<Misalignment>,
+
+    /// This data bit, close to the threshold, was flipped.
+    pub flipped_bit: Option<usize>,
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.clustering {
+            parts.push("clustering".to_string());
+        }
+        match self.misalignment {
+            Some(Misalignment::ExtraLeadingPulse) => parts.push("extra leading pulse".to_string()),
+            Some(Misalignment::MissingLeadingPulse) => {
+                parts.push("missing leading pulse".to_string());
+            }
+            None => {}
+        }
+        if let Some(bit) = self.flipped_bit {
+            parts.push(format!("bit {bit} flipped"));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A reading and the heuristics needed to decode it, `None` if the capture was clean.
+#[derive(Debug, Clone, Copy)]
+pub struct Decoded {
+    pub reading: Reading,
+    pub recovery: Option<Recovery>,
+}
+
+/// Number of bits closest to the threshold that may be flipped.
+const FLIP_CANDIDATES: usize = 3;
+
+/// Threshold splitting `highs` in two clusters, found with a 1-D k-means.
+fn cluster_threshold(highs: &[usize]) -> usize {
+    let (Some(&min), Some(&max)) = (highs.iter().min(), highs.iter().max()) else {
+        return 0;
+    };
+    let (mut short, mut long) = (min, max);
+    for _ in 0..16 {
+        let middle = midpoint(short, long);
+        let (shorts, longs): (Vec<usize>, Vec<usize>) =
+            highs.iter().partition(|&&high| high < middle);
+        let center = |cluster: &[usize], default| {
+            if cluster.is_empty() {
+                default
+            } else {
+                mean(cluster.iter().copied())
+            }
+        };
+        let next = (center(&shorts, short), center(&longs, long));
+        if next == (short, long) {
+            break;
+        }
+        (short, long) = next;
+    }
+    midpoint(short, long).max(1)
+}
+
+/// Decode `bits`, `None` standing for a bit that wasn't captured, trying both values for those.
+fn decode_bits(bits: &[Option<bool>; 40]) -> Option<[u8; 5]> {
+    // Only the last bit can be lost by realigning.
+    if bits.iter().filter(|bit| bit.is_none()).count() > 1 {
+        return None;
+    }
+    for guess in [false, true] {
+        let mut data = [0_u8; 5];
+        for (bit, value) in bits.iter().enumerate() {
+            data[bit / 8] <<= 1;
+            if value.unwrap_or(guess) {
+                data[bit / 8] |= 1;
+            }
+        }
+        if data[4] == checksum(data) {
+            return Some(data);
+        }
+    }
+    None
+}
+
+/// Decode a raw capture like [`decode`], falling back on the heuristics enabled in `options`
+/// when the checksum doesn't match.
+///
+/// # Errors
+/// Returns the error of [`decode`] if no heuristic recovers a valid reading.
+pub fn decode_with(arr: &Pulses, options: DecodeOptions) -> Result<Decoded, ReadingError> {
+    let error = match decode(arr) {
+        Ok(reading) => {
+            return Ok(Decoded {
+                reading,
+                recovery: None,
+            })
+        }
+        Err(e) => e,
+    };
+
+    let mut alignments = vec![(3, None)];
+    if options.realign {
+        alignments.push((1, Some(Misalignment::MissingLeadingPulse)));
+        alignments.push((5, Some(Misalignment::ExtraLeadingPulse)));
+    }
+
+    // Candidate decodings, from the least to the most invasive. The first one is the same as
+    // `decode`, it's only kept for the bit flips.
+    let mut candidates = Vec::new();
+    for (start, misalignment) in alignments {
+        let highs: Vec<usize> = arr
+            .iter()
+            .skip(start)
+            .step_by(2)
+            .take(40)
+            .copied()
+            .collect();
+        let lows: Vec<usize> = arr
+            .iter()
+            .skip(start - 1)
+            .step_by(2)
+            .take(40)
+            .copied()
+            .collect();
+        let mean = mean(lows.iter().copied());
+
+        if options.clustering {
+            let threshold = cluster_threshold(&highs);
+            candidates.push((highs.clone(), mean, false, misalignment));
+            candidates.push((highs, threshold, true, misalignment));
+        } else {
+            candidates.push((highs, mean, false, misalignment));
+        }
+    }
+
+    let attempts = candidates.iter().map(|candidate| (candidate, None)).chain(
+        candidates
+            .iter()
+            .filter(|_| options.bit_flip)
+            .flat_map(|candidate| {
+                let (highs, threshold, ..) = candidate;
+                let mut closest: Vec<usize> = (0..highs.len()).collect();
+                closest.sort_by_key(|&bit| highs[bit].abs_diff(*threshold));
+                closest
+                    .into_iter()
+                    .take(FLIP_CANDIDATES)
+                    .map(move |bit| (candidate, Some(bit)))
+            }),
+    );
+
+    for ((highs, threshold, clustering, misalignment), flipped_bit) in attempts {
+        let mut bits = [None; 40];
+        for (bit, &high) in highs.iter().enumerate() {
+            bits[bit] = Some((high >= *threshold) != (flipped_bit == Some(bit)));
+        }
+        if let Some(data) = decode_bits(&bits) {
+            return Ok(Decoded {
+                reading: to_reading(data),
+                recovery: Some(Recovery {
+                    clustering: *clustering,
+                    misalignment: *misalignment,
+                    flipped_bit,
+                }),
+            });
+        }
+    }
+
+    Err(error)
+}
+
+/// Decode a raw capture, possibly truncated by a timeout, and report how close each bit was to
+/// the threshold.
+#[must_use]
+pub fn analyze(pulses: &[usize]) -> Analysis {
+    let threshold = threshold(pulses);
+    let signed = |length: usize| i64::try_from(length).unwrap_or(i64::MAX);
+    let margins = pulses
+        .iter()
+        .skip(3)
+        .step_by(2)
+        .map(|&high| signed(high) - signed(threshold))
+        .collect();
+
+    let result = <&Pulses>::try_from(pulses).map_or_else(
+        |_| {
+            Err(ReadingError::Timeout {
+                phase: timeout_phase(pulses.len()),
+                pulses: pulses.to_vec(),
+            })
+        },
+        decode,
+    );
+
+    Analysis {
+        threshold,
+        margins,
+        result,
+    }
 }
 
 /// Read temperature and humidity from a DHT22 connected to a Gpio pin on a Raspberry Pi.
@@ -103,6 +441,134 @@ fn decode(arr: &[usize; DHT_PULSES * 2]) -> Result<Reading, ReadingError> {
 /// # Errors
 /// Returns a `ReadingError` if there's an error when reading.
 pub fn read(pin: u8) -> Result<Reading, ReadingError> {
+    decode(&capture(pin)?)
+}
+
+/// Run the timing-critical or blocking `f` on a thread of the blocking pool, so that it doesn't
+/// block the other tasks of the runtime.
+///
+/// # Errors
+/// Returns the error of `f`, or an I/O error if the task is cancelled, when the runtime shuts
+/// down.
+///
+/// # Panics
+/// Panics with the panic of `f`, if it panicked.
+pub async fn run_blocking<T, E, F>(f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<ReadingError> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => match e.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            Err(e) => Err(ReadingError::Io(io::Error::new(io::ErrorKind::Interrupted, e)).into()),
+        },
+    }
+}
+
+/// Asynchronous version of [`read`], for use within a tokio runtime.
+///
+/// The capture runs on a blocking thread, so the other tasks keep running during the 520 ms
+/// it takes.
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+pub async fn read_async(pin: u8) -> Result<Reading, ReadingError> {
+    decode(&capture_async(pin).await?)
+}
+
+/// A DHT22 on a GPIO pin, as a [`Sensor`].
+#[derive(Debug, Clone, Copy)]
+pub struct Dht22 {
+    pin: u8,
+    options: DecodeOptions,
+    last_read: Option<Instant>,
+}
+
+impl Dht22 {
+    /// The sensor doesn't answer when it's read more often.
+    pub const MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// The DHT22 on `pin`, decoded with the heuristics of `options`.
+    #[must_use]
+    pub const fn new(pin: u8, options: DecodeOptions) -> Self {
+        Self {
+            pin,
+            options,
+            last_read: None,
+        }
+    }
+}
+
+impl Sensor for Dht22 {
+    fn name(&self) -> &'static str {
+        "DHT22"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Temperature, Quantity::Humidity]
+    }
+
+    fn min_interval(&self) -> Duration {
+        Self::MIN_INTERVAL
+    }
+
+    /// Capture and decode a reading, see [`read_async`].
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        Box::pin(async move {
+            let remaining = self
+                .last_read
+                .map(|at| Self::MIN_INTERVAL.saturating_sub(at.elapsed()));
+            if let Some(remaining) = remaining.filter(|remaining| !remaining.is_zero()) {
+                return Err(SensorError::TooSoon(remaining));
+            }
+            self.last_read = Some(Instant::now());
+            let pulses = capture_async(self.pin).await?;
+            Ok(decode_with(&pulses, self.options)?.reading.into())
+        })
+    }
+}
+
+/// Asynchronous version of [`capture`], see [`read_async`].
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+pub async fn capture_async(pin: u8) -> Result<Pulses, ReadingError> {
+    capture_async_with(pin, None).await
+}
+
+/// Asynchronous version of [`capture`], running in real-time mode when `realtime` is set.
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+pub async fn capture_async_with(
+    pin: u8,
+    realtime: Option<RealtimeOptions>,
+) -> Result<Pulses, ReadingError> {
+    run_blocking(move || {
+        realtime.map_or_else(|| capture(pin), |options| capture_realtime(pin, &options))
+    })
+    .await
+}
+
+/// Capture the raw pulses of a reading with the thread in real-time mode, see
+/// [`realtime::enter`].
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading or if the real-time mode can't be
+/// entered.
+pub fn capture_realtime(pin: u8, options: &RealtimeOptions) -> Result<Pulses, ReadingError> {
+    let _guard = realtime::enter(options)?;
+    capture(pin)
+}
+
+/// Capture the raw pulses of a reading without decoding them, see [`read`].
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+pub fn capture(pin: u8) -> Result<Pulses, ReadingError> {
     let mut gpio = match Gpio::new() {
         Err(e) => return Err(ReadingError::Gpio(e)),
         Ok(g) => match g.get(pin) {
@@ -111,8 +577,6 @@ pub fn read(pin: u8) -> Result<Reading, ReadingError> {
         },
     };
 
-    let mut pulse_counts: [usize; DHT_PULSES * 2] = [0; DHT_PULSES * 2];
-
     gpio.write(Level::High);
     sleep(Duration::from_millis(500));
 
@@ -124,43 +588,202 @@ pub fn read(pin: u8) -> Result<Reading, ReadingError> {
     // Sometimes the pin is briefly low.
     tiny_sleep();
 
+    count_pulses(|| Ok(gpio.read() == Level::High))
+}
+
+/// Capture the raw pulses of a reading through the GPIO character device, see [`capture`].
+///
+/// The level is read with an ioctl, so the pulse lengths are smaller than with `rppal`.
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+#[cfg(feature = "cdev")]
+pub fn capture_line(line: &LineSpec) -> Result<Pulses, ReadingError> {
+    // Looked up beforehand: the sensor answers 20 to 40 µs after the start signal, leaving only
+    // the time of a request ioctl to switch the line to an input.
+    let line = line.find()?;
+    let output = cdev::request_output(&line, 1)?;
+    sleep(Duration::from_millis(500));
+
+    output.set_value(0)?;
+    sleep(Duration::from_millis(20));
+
+    drop(output);
+    let input = cdev::request_input(&line, None)?;
+
+    tiny_sleep();
+
+    count_pulses(|| Ok(input.get_value()? == 1))
+}
+
+/// Asynchronous version of [`capture_line`], running in real-time mode when `realtime` is set.
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+#[cfg(feature = "cdev")]
+pub async fn capture_line_async(
+    line: LineSpec,
+    realtime: Option<RealtimeOptions>,
+) -> Result<Pulses, ReadingError> {
+    run_blocking(move || {
+        let _guard = realtime.as_ref().map(realtime::enter).transpose()?;
+        capture_line(&line)
+    })
+    .await
+}
+
+/// Measure the answer of the sensor once it pulled the line low, `is_high` reading the level.
+fn count_pulses<F>(mut is_high: F) -> Result<Pulses, ReadingError>
+where
+    F: FnMut() -> Result<bool, ReadingError>,
+{
+    let mut pulse_counts: Pulses = [0; DHT_PULSES * 2];
     let mut count: usize = 0;
 
-    while gpio.read() == Level::High {
+    while is_high()? {
         count += 1;
 
         if count > MAX_COUNT {
-            return Result::Err(ReadingError::Timeout);
+            return Result::Err(ReadingError::Timeout {
+                phase: TimeoutPhase::StartHandshake,
+                pulses: Vec::new(),
+            });
         }
     }
 
     for c in 0..DHT_PULSES {
         let i = c * 2;
 
-        while gpio.read() == Level::Low {
+        // The first pulse is the answer of the sensor, the next ones are data bits.
+        let (low_phase, high_phase) = if c == 0 {
+            (TimeoutPhase::StartHandshake, TimeoutPhase::StartHandshake)
+        } else {
+            (TimeoutPhase::BitLow(c - 1), TimeoutPhase::BitHigh(c - 1))
+        };
+
+        while !is_high()? {
             pulse_counts[i] += 1;
 
             if pulse_counts[i] > MAX_COUNT {
-                return Result::Err(ReadingError::Timeout);
+                return Result::Err(ReadingError::Timeout {
+                    phase: low_phase,
+                    pulses: pulse_counts[..=i].to_vec(),
+                });
             }
         }
 
-        while gpio.read() == Level::High {
+        while is_high()? {
             pulse_counts[i + 1] += 1;
 
             if pulse_counts[i + 1] > MAX_COUNT {
-                return Result::Err(ReadingError::Timeout);
+                return Result::Err(ReadingError::Timeout {
+                    phase: high_phase,
+                    pulses: pulse_counts[..=i + 1].to_vec(),
+                });
             }
         }
     }
 
-    decode(&pulse_counts)
+    Ok(pulse_counts)
 }
 
 #[cfg(test)]
+#[allow(clippy::float_cmp)]
 mod tests {
-    use super::decode;
-    use super::ReadingError;
+    use super::{analyze, decode, decode_with, run_blocking, DecodeOptions, Misalignment, Pulses};
+    use super::{Dht22, ReadingError, TimeoutPhase};
+    use crate::{
+        sensor::{Sensor, SensorError},
+        simulated::{frame, pulses, PulseLengths},
+    };
+
+    use proptest::{collection::vec, prelude::*};
+
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// 65.2% and 35.1°C.
+    const DATA: [u8; 5] = [0x02, 0x8c, 0x01, 0x5f, 0xee];
+
+    fn encode(data: [u8; 5], low: usize, short: usize, long: usize) -> Pulses {
+        let lengths = PulseLengths {
+            low,
+            zero: short,
+            one: long,
+            ..PulseLengths::NOMINAL
+        };
+        pulses(data, lengths, |length| length)
+    }
+
+    /// Largest deviation of a pulse length in the generated captures, in microseconds. A low
+    /// period stays longer than the high period of a 0 and shorter than the one of a 1.
+    const JITTER: isize = 8;
+
+    /// A capture of `data` counted `scale` times faster than the microseconds of the spec,
+    /// each pulse deviating by `jitter`.
+    fn jittered(data: [u8; 5], scale: isize, jitter: &[isize]) -> Pulses {
+        let mut jitter = jitter.iter();
+        pulses(data, PulseLengths::NOMINAL, |length| {
+            let jitter = jitter.next().copied().unwrap_or_default();
+            (length * scale.unsigned_abs()).saturating_add_signed(jitter * scale)
+        })
+    }
+
+    /// A humidity and a temperature in tenths over the range of the DHT22, and a capture of
+    /// them.
+    fn capture() -> impl Strategy<Value = (u16, i16, Pulses)> {
+        (
+            0..=1000_u16,
+            -400..=800_i16,
+            1..=10_isize,
+            vec(-JITTER..=JITTER, 82),
+        )
+            .prop_map(|(humidity, temperature, scale, jitter)| {
+                let pulses = jittered(frame(humidity, temperature), scale, &jitter);
+                (humidity, temperature, pulses)
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn decode_round_trip((humidity, temperature, pulses) in capture()) {
+            let reading = decode(&pulses).unwrap();
+            prop_assert_eq!(reading.humidity, f32::from(humidity) / 10.0);
+            prop_assert_eq!(reading.temperature, f32::from(temperature) / 10.0);
+        }
+
+        #[test]
+        fn decode_rejects_a_flipped_bit(
+            humidity in 0..=1000_u16,
+            temperature in -400..=800_i16,
+            scale in 1..=10_isize,
+            jitter in vec(-JITTER..=JITTER, 82),
+            bit in 0..40_usize,
+        ) {
+            let mut corrupted = frame(humidity, temperature);
+            corrupted[bit / 8] ^= 0x80 >> (bit % 8);
+            let pulses = jittered(corrupted, scale, &jitter);
+            let is_checksum = matches!(
+                decode(&pulses),
+                Err(ReadingError::Checksum { data, .. }) if data == corrupted
+            );
+            prop_assert!(is_checksum);
+        }
+
+        #[test]
+        fn decode_never_panics(pulses in vec(any::<usize>(), 82), len in 0..=82_usize) {
+            let capture: Pulses = pulses.try_into().unwrap();
+            let _ = decode(&capture);
+            let _ = decode_with(&capture, DecodeOptions::ALL);
+            let _ = analyze(&capture[..len]);
+        }
+    }
 
     #[test]
     fn from_spec_positive_temp() {
@@ -215,16 +838,26 @@ mod tests {
             Ok(_) => {
                 panic!("should have failed");
             }
-            Err(e) => {
-                match e {
-                    ReadingError::Checksum => {
-                        // ok
-                    }
-                    _ => {
-                        panic!("should have Checksum, got {e:?} instead");
-                    }
+            Err(e) => match &e {
+                ReadingError::Checksum {
+                    pulses,
+                    data,
+                    expected,
+                    actual,
+                } => {
+                    assert_eq!(pulses, &arr);
+                    assert_eq!(data, &[0x0a, 0x8c, 0x80, 0x65, 0x73]);
+                    assert_eq!(*expected, 0x7b);
+                    assert_eq!(*actual, 0x73);
+                    assert_eq!(
+                        e.to_string(),
+                        "checksum mismatch: expected 0x7b, got 0x73 (data [0a, 8c, 80, 65, 73])"
+                    );
                 }
-            }
+                _ => {
+                    panic!("should have Checksum, got {e:?} instead");
+                }
+            },
         }
     }
 
@@ -247,4 +880,178 @@ mod tests {
         assert_eq!(x.humidity, 60.7);
         assert_eq!(x.temperature, 12.4);
     }
+
+    #[test]
+    fn analyze_sample1() {
+        let arr = [
+            458, 328, 320, 101, 249, 153, 314, 153, 320, 154, 317, 153, 316, 153, 321, 431, 320,
+            147, 397, 154, 315, 435, 316, 154, 320, 431, 320, 430, 319, 431, 320, 431, 320, 426,
+            401, 148, 319, 154, 316, 154, 320, 150, 320, 154, 315, 154, 320, 149, 320, 148, 397,
+            154, 319, 430, 321, 430, 321, 431, 320, 429, 318, 432, 320, 150, 320, 147, 379, 434,
+            316, 434, 317, 153, 320, 431, 317, 435, 316, 435, 317, 153, 320, 425,
+        ];
+
+        let analysis = analyze(&arr);
+        assert_eq!(analysis.threshold, 324);
+        assert_eq!(analysis.margins.len(), 40);
+        assert_eq!(analysis.margins[0], 101 - 324);
+        assert_eq!(analysis.weakest_bit(), Some((39, 425 - 324)));
+        assert!(analysis.result.is_ok());
+    }
+
+    #[test]
+    fn analyze_truncated() {
+        let analysis = analyze(&[80, 80, 50, 26, 50]);
+        assert_eq!(analysis.margins, [26 - 50]);
+        match analysis.result {
+            Err(ReadingError::Timeout { phase, pulses }) => {
+                assert_eq!(phase, TimeoutPhase::BitLow(1));
+                assert_eq!(pulses.len(), 5);
+            }
+            other => panic!("should have timed out, got {other:?} instead"),
+        }
+        assert!(matches!(
+            analyze(&[80, 80, 50, 26]).result,
+            Err(ReadingError::Timeout {
+                phase: TimeoutPhase::BitHigh(0),
+                ..
+            })
+        ));
+        assert!(matches!(
+            analyze(&[80]).result,
+            Err(ReadingError::Timeout {
+                phase: TimeoutPhase::StartHandshake,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn decode_options() {
+        assert_eq!("".parse(), Ok(DecodeOptions::default()));
+        assert_eq!("all".parse(), Ok(DecodeOptions::ALL));
+        assert_eq!(
+            "clustering, bit_flip".parse(),
+            Ok(DecodeOptions {
+                clustering: true,
+                realign: false,
+                bit_flip: true,
+            })
+        );
+        assert!("magic".parse::<DecodeOptions>().is_err());
+    }
+
+    #[test]
+    fn recover_clean() {
+        let decoded = decode_with(&encode(DATA, 50, 26, 70), DecodeOptions::ALL).unwrap();
+        assert_eq!(decoded.recovery, None);
+        assert!(decoded.reading.temperature == 35.1);
+    }
+
+    #[test]
+    fn recover_with_clustering() {
+        let arr = encode(DATA, 50, 55, 100);
+        assert!(decode_with(&arr, DecodeOptions::default()).is_err());
+
+        let options = DecodeOptions {
+            clustering: true,
+            ..Default::default()
+        };
+        let recovery = decode_with(&arr, options).unwrap().recovery.unwrap();
+        assert!(recovery.clustering);
+        assert_eq!(recovery.misalignment, None);
+        assert_eq!(recovery.flipped_bit, None);
+    }
+
+    #[test]
+    fn recover_missing_leading_pulse() {
+        let clean = encode(DATA, 50, 26, 70);
+        let mut arr = [0; 82];
+        arr[..80].copy_from_slice(&clean[2..]);
+        arr[80..].copy_from_slice(&[50, 26]);
+
+        let options = DecodeOptions {
+            realign: true,
+            ..Default::default()
+        };
+        let decoded = decode_with(&arr, options).unwrap();
+        assert_eq!(
+            decoded.recovery.unwrap().misalignment,
+            Some(Misalignment::MissingLeadingPulse)
+        );
+        assert!(decoded.reading.humidity == 65.2);
+    }
+
+    #[test]
+    fn recover_extra_leading_pulse() {
+        let clean = encode(DATA, 50, 26, 70);
+        let mut arr = [0; 82];
+        arr[..2].copy_from_slice(&[30, 40]);
+        arr[2..].copy_from_slice(&clean[..80]);
+
+        let options = DecodeOptions {
+            realign: true,
+            ..Default::default()
+        };
+        let decoded = decode_with(&arr, options).unwrap();
+        assert_eq!(
+            decoded.recovery.unwrap().misalignment,
+            Some(Misalignment::ExtraLeadingPulse)
+        );
+        assert!(decoded.reading.temperature == 35.1);
+    }
+
+    #[test]
+    fn recover_with_bit_flip() {
+        let mut arr = encode(DATA, 50, 26, 70);
+        // Bit 6 is a 1 whose high period is just below the threshold.
+        arr[3 + 6 * 2] = 49;
+        assert!(decode_with(&arr, DecodeOptions::default()).is_err());
+
+        let options = DecodeOptions {
+            bit_flip: true,
+            ..Default::default()
+        };
+        let decoded = decode_with(&arr, options).unwrap();
+        assert_eq!(decoded.recovery.unwrap().flipped_bit, Some(6));
+        assert!(decoded.reading.humidity == 65.2);
+    }
+
+    #[tokio::test]
+    async fn blocking_capture_does_not_starve_the_runtime() {
+        let ticked = Arc::new(AtomicBool::new(false));
+        let timer = tokio::spawn({
+            let ticked = ticked.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ticked.store(true, Ordering::SeqCst);
+            }
+        });
+
+        let ticked_during_capture = run_blocking({
+            let ticked = ticked.clone();
+            move || {
+                thread::sleep(Duration::from_millis(200));
+                Ok::<_, ReadingError>(ticked.load(Ordering::SeqCst))
+            }
+        })
+        .await
+        .unwrap();
+
+        timer.await.unwrap();
+        assert!(ticked_during_capture);
+    }
+
+    #[tokio::test]
+    async fn min_interval() {
+        // Just read, the sensor isn't captured again.
+        let mut sensor = Dht22 {
+            last_read: Some(Instant::now()),
+            ..Dht22::new(4, DecodeOptions::default())
+        };
+        assert!(matches!(
+            sensor.read().await,
+            Err(SensorError::TooSoon(remaining)) if remaining <= Dht22::MIN_INTERVAL
+        ));
+    }
 }
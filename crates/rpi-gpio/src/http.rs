@@ -0,0 +1,150 @@
+//! A minimal HTTP server used to expose the state of a running service on the local network.
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tracing::{debug, info};
+
+use std::{io, sync::Arc, time::Duration};
+
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time given to a client to send the headers of its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The body returned for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    #[must_use]
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self {
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub const fn prometheus(body: String) -> Self {
+        Self {
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+}
+
+/// Parse the request line of an HTTP request, returning the method and the path without the
+/// query string.
+fn parse_request_line(request: &str) -> Option<(&str, &str)> {
+    let mut parts = request.lines().next()?.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    parts.next()?.starts_with("HTTP/").then_some(())?;
+    let path = target.split('?').next().unwrap_or(target);
+    Some((method, path))
+}
+
+fn format_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+async fn handle<F>(mut stream: TcpStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&str) -> Option<Response> + Sync,
+{
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0_u8; 1024];
+    let headers = async {
+        while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(false);
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+            if buffer.len() > MAX_REQUEST_SIZE {
+                break;
+            }
+        }
+        Ok::<_, io::Error>(true)
+    };
+    let received = timeout(REQUEST_TIMEOUT, headers)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request headers timed out"))??;
+    if !received {
+        return Ok(());
+    }
+
+    let request = String::from_utf8_lossy(&buffer);
+    let response = match parse_request_line(&request) {
+        None => format_response("400 Bad Request", "text/plain", "bad request\n"),
+        Some((method, path)) => {
+            debug!("{method} {path}");
+            if method == "GET" {
+                handler(path).map_or_else(
+                    || format_response("404 Not Found", "text/plain", "not found\n"),
+                    |response| format_response("200 OK", response.content_type, &response.body),
+                )
+            } else {
+                format_response(
+                    "405 Method Not Allowed",
+                    "text/plain",
+                    "method not allowed\n",
+                )
+            }
+        }
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Listen on `addr` and answer `GET` requests with `handler`, which returns `None` for unknown
+/// paths.
+///
+/// # Errors
+/// Returns the error if the address can't be bound.
+pub async fn serve<F>(addr: &str, handler: F) -> io::Result<()>
+where
+    F: Fn(&str) -> Option<Response> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, handler.as_ref()).await {
+                debug!("HTTP connection with {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_request_line;
+
+    #[test]
+    fn request_line() {
+        assert_eq!(
+            parse_request_line("GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            Some(("GET", "/status"))
+        );
+        assert_eq!(
+            parse_request_line("GET /status?pretty HTTP/1.0\r\n\r\n"),
+            Some(("GET", "/status"))
+        );
+        assert_eq!(parse_request_line("GET /status\r\n\r\n"), None);
+        assert_eq!(parse_request_line(""), None);
+    }
+}
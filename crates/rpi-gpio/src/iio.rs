@@ -0,0 +1,212 @@
+//! DHT22 readings through the `dht11` kernel driver.
+//!
+//! With `dtoverlay=dht11,gpiopin=N` in `config.txt`, the kernel decodes the sensor itself and
+//! exposes the readings in an IIO device, which is far more reliable than bit-banging from
+//! userspace:
+//!
+//! ```text
+//! /sys/bus/iio/devices/iio:device0/name                        dht11@4
+//! /sys/bus/iio/devices/iio:device0/in_temp_input               21500 (m°C)
+//! /sys/bus/iio/devices/iio:device0/in_humidityrelative_input   45300 (m%)
+//! ```
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    dht22::{Dht22, Reading},
+    sensor::{BoxFuture, Measurements, Quantity, Sensor, SensorError},
+    ReadingError, TimeoutPhase,
+};
+
+/// Where the kernel lists the IIO devices.
+pub const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+const DRIVER_NAME: &str = "dht11";
+const TEMPERATURE: &str = "in_temp_input";
+const HUMIDITY: &str = "in_humidityrelative_input";
+
+/// A sensor handled by the `dht11` kernel driver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IioDht {
+    device: PathBuf,
+}
+
+impl IioDht {
+    /// The sensor of the IIO device directory `device`, such as
+    /// `/sys/bus/iio/devices/iio:device0`.
+    pub fn new(device: impl Into<PathBuf>) -> Self {
+        Self {
+            device: device.into(),
+        }
+    }
+
+    /// The first device of `devices` handled by the `dht11` driver, see [`IIO_DEVICES`].
+    ///
+    /// # Errors
+    /// Returns the error if `devices` can't be listed, `NotFound` if there's no such device.
+    pub fn find(devices: impl AsRef<Path>) -> io::Result<Self> {
+        let devices = devices.as_ref();
+        let mut entries = fs::read_dir(devices)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        entries
+            .into_iter()
+            .find(|device| {
+                fs::read_to_string(device.join("name"))
+                    .is_ok_and(|name| name.trim().starts_with(DRIVER_NAME))
+            })
+            .map(Self::new)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "no {DRIVER_NAME} device in {}, is the overlay loaded?",
+                        devices.display()
+                    ),
+                )
+            })
+    }
+
+    #[must_use]
+    pub fn device(&self) -> &Path {
+        &self.device
+    }
+
+    /// Read the sensor, blocking while the driver talks to it.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Timeout` if the sensor didn't answer or the driver couldn't decode
+    /// the answer, and `ReadingError::Io` if the device can't be read.
+    pub fn read(&self) -> Result<Reading, ReadingError> {
+        Ok(Reading {
+            temperature: self.channel(TEMPERATURE)?,
+            humidity: self.channel(HUMIDITY)?,
+            pressure: None,
+        })
+    }
+
+    /// Asynchronous version of [`IioDht::read`], running on the blocking pool.
+    ///
+    /// # Errors
+    /// See [`IioDht::read`].
+    pub async fn read_async(&self) -> Result<Reading, ReadingError> {
+        let sensor = self.clone();
+        crate::dht22::run_blocking(move || sensor.read()).await
+    }
+
+    /// Value of a channel, converted from thousandths.
+    fn channel(&self, channel: &str) -> Result<f32, ReadingError> {
+        let path = self.device.join(channel);
+        let raw = fs::read_to_string(&path).map_err(from_io)?;
+        raw.trim()
+            .parse::<i32>()
+            .map(|value| {
+                #[allow(clippy::cast_precision_loss)]
+                let value = value as f32;
+                value / 1000.0
+            })
+            .map_err(|e| {
+                ReadingError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {e}", path.display()),
+                ))
+            })
+    }
+}
+
+/// The driver fails with `ETIMEDOUT` when the sensor doesn't answer, and with `EIO` when the
+/// handshake fails, the answer is truncated or has a wrong checksum. It doesn't tell them apart,
+/// so both are timeouts: like a failed capture, they come from the wiring or noise on the line,
+/// not from the device.
+fn from_io(e: io::Error) -> ReadingError {
+    match e.raw_os_error() {
+        Some(libc::ETIMEDOUT | libc::EIO) => ReadingError::Timeout {
+            phase: TimeoutPhase::StartHandshake,
+            pulses: Vec::new(),
+        },
+        _ => ReadingError::Io(e),
+    }
+}
+
+impl Sensor for IioDht {
+    fn name(&self) -> &'static str {
+        "DHT22"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Temperature, Quantity::Humidity]
+    }
+
+    /// The driver answers with its last reading when it's read more often.
+    fn min_interval(&self) -> Duration {
+        Dht22::MIN_INTERVAL
+    }
+
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        Box::pin(async move { Ok(self.read_async().await?.into()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_io, IioDht};
+    use crate::{temp_dir::TempDir, ReadingError};
+
+    use std::{fs, io};
+
+    fn fake_sysfs() -> TempDir {
+        let dir = TempDir::new();
+        for (device, name) in [("iio:device0", "mcp3008"), ("iio:device1", "dht11@4")] {
+            let device = dir.path().join(device);
+            fs::create_dir_all(&device).unwrap();
+            fs::write(device.join("name"), format!("{name}\n")).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn read() {
+        let dir = fake_sysfs();
+        let root = dir.path();
+        let device = root.join("iio:device1");
+        fs::write(device.join("in_temp_input"), "-2300\n").unwrap();
+        fs::write(device.join("in_humidityrelative_input"), "45300\n").unwrap();
+
+        let sensor = IioDht::find(root).unwrap();
+        assert_eq!(sensor.device(), device);
+        let reading = sensor.read().unwrap();
+
+        assert!((reading.temperature + 2.3).abs() < 1e-6);
+        assert!((reading.humidity - 45.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = fake_sysfs();
+        let root = dir.path();
+        fs::remove_dir_all(root.join("iio:device1")).unwrap();
+        let missing = IioDht::find(root).unwrap_err();
+        fs::write(root.join("iio:device0").join("in_temp_input"), "garbage").unwrap();
+        let invalid = IioDht::new(root.join("iio:device0")).read().unwrap_err();
+
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(invalid, ReadingError::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+        assert!(matches!(
+            from_io(io::Error::from_raw_os_error(libc::ETIMEDOUT)),
+            ReadingError::Timeout { .. }
+        ));
+        assert_eq!(
+            from_io(io::Error::from_raw_os_error(libc::EIO)).kind(),
+            "timeout"
+        );
+        assert_eq!(
+            from_io(io::Error::from_raw_os_error(libc::EACCES)).kind(),
+            "io"
+        );
+    }
+}
@@ -1,16 +1,213 @@
+pub mod adc;
+pub mod binary;
+pub mod bme280;
+pub mod bus;
+pub mod calibration;
+#[cfg(feature = "cdev")]
+pub mod cdev;
 pub mod dht22;
+pub mod http;
+pub mod iio;
 pub mod light;
+pub mod lux;
+pub mod metrics;
+pub mod onewire;
+pub mod realtime;
+pub mod record;
+pub mod sensor;
+pub mod sht31;
+pub mod simulated;
+pub mod status;
+#[cfg(test)]
+mod temp_dir;
 pub mod tls;
 
+use std::{fmt, str::FromStr};
+
+/// How the GPIO lines are accessed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Memory-mapped registers of the Raspberry Pi, through `rppal`.
+    #[default]
+    Rppal,
+
+    /// The Linux character device, available on any board, see [`cdev`].
+    #[cfg(feature = "cdev")]
+    Cdev,
+
+    /// No hardware at all, see [`simulated`].
+    Simulated,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rppal" => Ok(Self::Rppal),
+            #[cfg(feature = "cdev")]
+            "cdev" => Ok(Self::Cdev),
+            #[cfg(not(feature = "cdev"))]
+            "cdev" => Err("the cdev backend needs the cdev feature".to_string()),
+            "simulated" => Ok(Self::Simulated),
+            _ => Err(format!(
+                "unknown backend {s}, expected rppal, cdev or simulated"
+            )),
+        }
+    }
+}
+
+impl Backend {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Rppal => "rppal",
+            #[cfg(feature = "cdev")]
+            Self::Cdev => "cdev",
+            Self::Simulated => "simulated",
+        }
+    }
+}
+
+/// Internal resistor of an input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pull {
+    #[default]
+    Floating,
+    Up,
+    Down,
+}
+
+impl FromStr for Pull {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "floating" => Ok(Self::Floating),
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            _ => Err(format!("unknown pull {s}, expected floating, up or down")),
+        }
+    }
+}
+
+impl Pull {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Floating => "floating",
+            Self::Up => "up",
+            Self::Down => "down",
+        }
+    }
+}
+
+/// Step of the DHT22 protocol during which the sensor stopped answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Waiting for the sensor to answer the start signal.
+    StartHandshake,
+
+    /// Low period preceding the data bit `n`, from 0 to 39.
+    BitLow(usize),
+
+    /// High period encoding the data bit `n`, from 0 to 39.
+    BitHigh(usize),
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StartHandshake => write!(f, "start handshake"),
+            Self::BitLow(n) => write!(f, "bit {n} low"),
+            Self::BitHigh(n) => write!(f, "bit {n} high"),
+        }
+    }
+}
+
 /// Errors that may occur when reading temperature.
 #[derive(Debug)]
 pub enum ReadingError {
-    /// Occurs if a timeout occured reading the pin.
-    Timeout,
+    /// Occurs if a timeout occured reading the pin, or if the kernel driver failed to read the
+    /// sensor.
+    Timeout {
+        /// Where the sensor stopped answering.
+        phase: TimeoutPhase,
+        /// Pulse lengths captured before the timeout, alternating low and high periods.
+        pulses: Vec<usize>,
+    },
 
     /// Occurs if the checksum value from the DHT22 is incorrect.
-    Checksum,
+    Checksum {
+        /// Captured pulse lengths, alternating low and high periods.
+        pulses: Vec<usize>,
+        /// Decoded bytes: humidity, temperature and the transmitted checksum.
+        data: [u8; 5],
+        /// Checksum computed from the first four bytes.
+        expected: u8,
+        /// Checksum transmitted by the sensor.
+        actual: u8,
+    },
 
     /// Occurs if there is a problem accessing gpio itself on the Raspberry PI.
     Gpio(rppal::gpio::Error),
+
+    /// Occurs if a bus, the device of the kernel driver or the GPIO character device can't be
+    /// accessed.
+    Io(std::io::Error),
+
+    /// Occurs if the real-time mode of the capture can't be entered.
+    Realtime(std::io::Error),
+}
+
+impl ReadingError {
+    /// A short name for the kind of error.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Timeout { .. } => "timeout",
+            Self::Checksum { .. } => "checksum",
+            Self::Gpio(_) => "gpio",
+            Self::Io(_) => "io",
+            Self::Realtime(_) => "realtime",
+        }
+    }
+}
+
+impl fmt::Display for ReadingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout { phase, pulses } => write!(
+                f,
+                "timed out during {phase} after {} pulses",
+                pulses.len() / 2
+            ),
+            Self::Checksum {
+                data,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "checksum mismatch: expected {expected:#04x}, got {actual:#04x} (data {data:02x?})"
+            ),
+            Self::Gpio(e) => write!(f, "gpio error: {e}"),
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Realtime(e) if e.kind() == std::io::ErrorKind::PermissionDenied => write!(
+                f,
+                "real-time mode needs root or the CAP_SYS_NICE and CAP_IPC_LOCK capabilities ({e})"
+            ),
+            Self::Realtime(e) => write!(f, "real-time mode error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Gpio(e) => Some(e),
+            Self::Io(e) | Self::Realtime(e) => Some(e),
+            Self::Timeout { .. } | Self::Checksum { .. } => None,
+        }
+    }
 }
@@ -1,11 +1,349 @@
-use rppal::gpio::Gpio;
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, sleep_until, Instant},
+};
 
-use crate::ReadingError;
+#[cfg(feature = "cdev")]
+use crate::cdev::LineSpec;
+use crate::{
+    sensor::{BoxFuture, Measurement, Measurements, Quantity, Sensor, SensorError},
+    simulated::Script,
+    Pull, ReadingError,
+};
 
+use std::time::Duration;
+
+/// How the level of a digital input translates to its state, light for a light sensor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputOptions {
+    pub pull: Pull,
+
+    /// Whether the module is active-low, a low level meaning active.
+    pub inverted: bool,
+}
+
+impl InputOptions {
+    /// Whether the input is active when its level is `high`.
+    #[must_use]
+    pub const fn active(self, high: bool) -> bool {
+        high != self.inverted
+    }
+}
+
+fn input_pin(pin_num: u8, pull: Pull) -> Result<InputPin, ReadingError> {
+    let pin = Gpio::new()?.get(pin_num)?;
+    Ok(match pull {
+        Pull::Floating => pin.into_input(),
+        Pull::Up => pin.into_input_pullup(),
+        Pull::Down => pin.into_input_pulldown(),
+    })
+}
+
+/// Whether there's light.
+///
 /// # Errors
 /// Returns a `ReadingError` if there's an error when reading.
-pub fn read(pin_num: u8) -> Result<bool, ReadingError> {
-    let gpio = Gpio::new().map_err(ReadingError::Gpio)?;
-    let pin = gpio.get(pin_num).map_err(ReadingError::Gpio)?.into_input();
-    Ok(pin.is_high())
+pub fn read(pin_num: u8, options: InputOptions) -> Result<bool, ReadingError> {
+    let pin = input_pin(pin_num, options.pull)?;
+    Ok(options.active(pin.is_high()))
+}
+
+/// Read the light sensor through the GPIO character device, see [`read`].
+///
+/// # Errors
+/// Returns a `ReadingError` if there's an error when reading.
+#[cfg(feature = "cdev")]
+pub fn read_line(line: &LineSpec, options: InputOptions) -> Result<bool, ReadingError> {
+    let high = line.input(Some(options.pull))?.get_value()? == 1;
+    Ok(options.active(high))
+}
+
+/// A digital input read on demand, as a [`Sensor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigitalInput {
+    pin: u8,
+    options: InputOptions,
+}
+
+impl DigitalInput {
+    #[must_use]
+    pub const fn new(pin: u8, options: InputOptions) -> Self {
+        Self { pin, options }
+    }
+}
+
+impl Sensor for DigitalInput {
+    fn name(&self) -> &'static str {
+        "digital"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Light]
+    }
+
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        Box::pin(async move {
+            let light = read(self.pin, self.options)?;
+            Ok(Measurement::Light(light).into())
+        })
+    }
+}
+
+/// Settings of [`watch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WatchOptions {
+    pub input: InputOptions,
+
+    /// Edges closer than this to the previous one are ignored.
+    pub debounce: Option<Duration>,
+
+    /// How long a new level must hold before it's reported, so that slow edges don't chatter.
+    pub stable: Duration,
+}
+
+/// Turns the edges of the input into level changes once they're stable.
+#[derive(Debug)]
+struct Debouncer {
+    stable: Duration,
+    reported: bool,
+    /// Level of the last edge, and when it happened, until it's stable.
+    pending: Option<(bool, Instant)>,
+}
+
+impl Debouncer {
+    const fn new(level: bool, stable: Duration) -> Self {
+        Self {
+            stable,
+            reported: level,
+            pending: None,
+        }
+    }
+
+    /// An edge going back to the reported level still waits to be stable, since the edge
+    /// settling the input may have been dropped by the debouncing.
+    const fn edge(&mut self, level: bool, at: Instant) {
+        self.pending = Some((level, at));
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, at)| at + self.stable)
+    }
+
+    /// The new level, if the last edge held long enough at `now` and changed the level.
+    ///
+    /// `current` is the level read from the input, if it can be: it's the one reported rather
+    /// than the level of the last edge, which is stale when the debouncing dropped the next one.
+    fn settle(&mut self, now: Instant, current: Option<bool>) -> Option<bool> {
+        let (level, _) = self.pending?;
+        if self.deadline().is_some_and(|deadline| now < deadline) {
+            return None;
+        }
+        self.pending = None;
+        let level = current.unwrap_or(level);
+        (level != self.reported).then(|| {
+            self.reported = level;
+            level
+        })
+    }
+}
+
+/// State changes of a digital input, such as a light sensor, driven by interrupts.
+#[derive(Debug)]
+pub struct Watcher {
+    edges: mpsc::UnboundedReceiver<(bool, Instant)>,
+    debouncer: Debouncer,
+    /// Input of the `rppal` backend, keeping its interrupt registered, and its options.
+    pin: Option<(InputPin, InputOptions)>,
+}
+
+impl Watcher {
+    /// The last reported state.
+    #[must_use]
+    pub const fn level(&self) -> bool {
+        self.debouncer.reported
+    }
+
+    /// Wait for the next state change, `None` if the input can't be watched anymore.
+    pub async fn changed(&mut self) -> Option<bool> {
+        loop {
+            let deadline = self.debouncer.deadline();
+            tokio::select! {
+                edge = self.edges.recv() => {
+                    let (level, at) = edge?;
+                    self.debouncer.edge(level, at);
+                }
+                () = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {}
+            }
+            let current = self
+                .pin
+                .as_ref()
+                .map(|(pin, options)| options.active(pin.is_high()));
+            if let Some(level) = self.debouncer.settle(Instant::now(), current) {
+                return Some(level);
+            }
+        }
+    }
+}
+
+/// Watch the digital input on `pin` with an edge-triggered interrupt.
+///
+/// # Errors
+/// Returns a `ReadingError` if the interrupt can't be set up.
+pub fn watch(pin: u8, options: WatchOptions) -> Result<Watcher, ReadingError> {
+    let input_options = options.input;
+    let mut input = input_pin(pin, input_options.pull)?;
+    let level = input_options.active(input.is_high());
+    let (sender, edges) = mpsc::unbounded_channel();
+    input.set_async_interrupt(Trigger::Both, options.debounce, move |event| {
+        let active = input_options.active(event.trigger == Trigger::RisingEdge);
+        let _ = sender.send((active, Instant::now()));
+    })?;
+
+    Ok(Watcher {
+        edges,
+        debouncer: Debouncer::new(level, options.stable),
+        pin: Some((input, input_options)),
+    })
+}
+
+/// Watch a simulated digital input following `script`, without hardware.
+///
+/// The input starts in the state of the first step. The edges go through the debouncing of
+/// `options`, but the pull and the polarity don't apply.
+///
+/// # Panics
+/// Panics if it's called outside of a tokio runtime.
+#[must_use]
+pub fn watch_simulated(script: &Script, options: WatchOptions) -> Watcher {
+    let steps = script.steps().to_vec();
+    let level = steps[0].0;
+    let (sender, edges) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        for (index, (_, duration)) in steps.iter().enumerate().cycle() {
+            sleep(*duration).await;
+            let (next, _) = steps[(index + 1) % steps.len()];
+            if sender.send((next, Instant::now())).is_err() {
+                break;
+            }
+        }
+    });
+
+    Watcher {
+        edges,
+        debouncer: Debouncer::new(level, options.stable),
+        pin: None,
+    }
+}
+
+/// Watch a digital input through the GPIO character device, see [`watch`].
+///
+/// # Errors
+/// Returns a `ReadingError` if the line can't be requested.
+#[cfg(feature = "cdev")]
+pub fn watch_line(line: &LineSpec, options: WatchOptions) -> Result<Watcher, ReadingError> {
+    use gpio_cdev::EventType;
+
+    let input_options = options.input;
+    let events = line.events(Some(input_options.pull))?;
+    let level = input_options.active(events.get_value()? == 1);
+    let (sender, edges) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut last: Option<Instant> = None;
+        for event in events {
+            let Ok(event) = event else { break };
+            let at = Instant::now();
+            if let (Some(debounce), Some(last)) = (options.debounce, last) {
+                if at - last < debounce {
+                    continue;
+                }
+            }
+            last = Some(at);
+            let active = input_options.active(event.event_type() == EventType::RisingEdge);
+            if sender.send((active, at)).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Watcher {
+        edges,
+        debouncer: Debouncer::new(level, options.stable),
+        pin: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{watch_simulated, Debouncer, InputOptions, WatchOptions, Watcher};
+    use crate::simulated::Script;
+
+    use std::time::Duration;
+    use tokio::{sync::mpsc, time::Instant};
+
+    #[test]
+    fn debounce() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut debouncer = Debouncer::new(false, Duration::from_millis(50));
+
+        // A flicker shorter than the stable duration is ignored.
+        debouncer.edge(true, at(0));
+        debouncer.edge(false, at(10));
+        assert_eq!(debouncer.deadline(), Some(at(60)));
+        assert_eq!(debouncer.settle(at(100), None), None);
+        assert_eq!(debouncer.deadline(), None);
+
+        debouncer.edge(true, at(100));
+        assert_eq!(debouncer.settle(at(120), None), None);
+        assert_eq!(debouncer.deadline(), Some(at(150)));
+        assert_eq!(debouncer.settle(at(150), None), Some(true));
+        assert_eq!(debouncer.settle(at(200), None), None);
+
+        // The edge settling the input back to false was dropped, the level read wins.
+        debouncer.edge(false, at(300));
+        debouncer.edge(true, at(310));
+        assert_eq!(debouncer.settle(at(360), Some(false)), Some(false));
+
+        // And the other way around, when the last edge looks like a flicker.
+        debouncer.edge(true, at(400));
+        debouncer.edge(false, at(410));
+        assert_eq!(debouncer.settle(at(460), Some(true)), Some(true));
+    }
+
+    #[test]
+    fn inverted() {
+        let active_low = InputOptions {
+            inverted: true,
+            ..Default::default()
+        };
+        assert!(active_low.active(false));
+        assert!(!active_low.active(true));
+        assert!(InputOptions::default().active(true));
+    }
+
+    #[tokio::test]
+    async fn changes_are_reported_immediately() {
+        let (sender, edges) = mpsc::unbounded_channel();
+        let mut watcher = Watcher {
+            edges,
+            debouncer: Debouncer::new(false, Duration::ZERO),
+            pin: None,
+        };
+
+        sender.send((true, Instant::now())).unwrap();
+        assert_eq!(watcher.changed().await, Some(true));
+        assert!(watcher.level());
+        drop(sender);
+        assert_eq!(watcher.changed().await, None);
+    }
+
+    #[tokio::test]
+    async fn simulated() {
+        let script = "off:0.02,on:0.02".parse::<Script>().unwrap();
+        let mut watcher = watch_simulated(&script, WatchOptions::default());
+        assert!(!watcher.level());
+        assert_eq!(watcher.changed().await, Some(true));
+        assert_eq!(watcher.changed().await, Some(false));
+    }
 }
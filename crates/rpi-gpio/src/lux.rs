@@ -0,0 +1,260 @@
+//! Ambient light sensors measuring lux on I2C: the BH1750 and the TSL2561.
+use std::{io, thread::sleep, time::Duration};
+
+use crate::{
+    bus::I2cBus,
+    sensor::{BlockingSensor, Measurement, Measurements, Quantity},
+    ReadingError,
+};
+
+/// An illuminance measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightReading {
+    pub lux: f32,
+}
+
+/// A sensor measuring illuminance, one conversion at a time.
+pub trait LuxSensor {
+    /// Start a conversion.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the sensor doesn't answer.
+    fn start(&mut self) -> Result<(), ReadingError>;
+
+    /// How long a conversion takes at most.
+    fn conversion_time(&self) -> Duration;
+
+    /// The result of the last conversion.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the sensor doesn't answer or is saturated.
+    fn fetch(&mut self) -> Result<LightReading, ReadingError>;
+
+    /// Start a conversion and wait for its result.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the sensor doesn't answer or is saturated.
+    fn read(&mut self) -> Result<LightReading, ReadingError> {
+        self.start()?;
+        sleep(self.conversion_time());
+        self.fetch()
+    }
+}
+
+/// 16-bit ambient light sensor, from 1 to 65535 lx.
+#[derive(Debug)]
+pub struct Bh1750<B> {
+    bus: B,
+    address: u8,
+}
+
+impl<B: I2cBus> Bh1750<B> {
+    /// Address with the ADDR pin low, 0x5c when it's high.
+    pub const DEFAULT_ADDRESS: u8 = 0x23;
+
+    const POWER_ON: u8 = 0x01;
+    /// Single measurement at a 1 lx resolution, powering down afterwards.
+    const ONE_TIME_HIGH_RESOLUTION: u8 = 0x20;
+    /// Counts per lux with the default measurement time.
+    const COUNTS_PER_LUX: f32 = 1.2;
+
+    pub const fn new(bus: B, address: u8) -> Self {
+        Self { bus, address }
+    }
+}
+
+impl<B: I2cBus> LuxSensor for Bh1750<B> {
+    fn start(&mut self) -> Result<(), ReadingError> {
+        self.bus.write(self.address, &[Self::POWER_ON])?;
+        self.bus
+            .write(self.address, &[Self::ONE_TIME_HIGH_RESOLUTION])
+    }
+
+    fn conversion_time(&self) -> Duration {
+        Duration::from_millis(180)
+    }
+
+    fn fetch(&mut self) -> Result<LightReading, ReadingError> {
+        let mut raw = [0; 2];
+        self.bus.read(self.address, &mut raw)?;
+        Ok(LightReading {
+            lux: f32::from(u16::from_be_bytes(raw)) / Self::COUNTS_PER_LUX,
+        })
+    }
+}
+
+impl<B: I2cBus + Send + 'static> BlockingSensor for Bh1750<B> {
+    fn name(&self) -> &'static str {
+        "BH1750"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Illuminance]
+    }
+
+    fn read_blocking(&mut self) -> Result<Measurements, ReadingError> {
+        Ok(Measurement::Illuminance(LuxSensor::read(self)?.lux).into())
+    }
+}
+
+/// Light-to-digital converter with a broadband and an infrared photodiode, from 0.1 to 40000 lx.
+#[derive(Debug)]
+pub struct Tsl2561<B> {
+    bus: B,
+    address: u8,
+}
+
+impl<B: I2cBus> Tsl2561<B> {
+    /// Address with the ADDR SEL pin floating, 0x29 when it's low and 0x49 when it's high.
+    pub const DEFAULT_ADDRESS: u8 = 0x39;
+
+    const COMMAND: u8 = 0x80;
+    /// Reads a 16-bit register in a single transaction.
+    const WORD: u8 = 0x20;
+    const CONTROL: u8 = 0x00;
+    const TIMING: u8 = 0x01;
+    const DATA0: u8 = 0x0c;
+    const DATA1: u8 = 0x0e;
+    const POWER_ON: u8 = 0x03;
+    /// Gain of 1, for daylight, and a 402 ms integration time.
+    const LOW_GAIN_402MS: u8 = 0x02;
+    /// The lux formula assumes a gain of 16.
+    const GAIN_SCALE: f32 = 16.0;
+
+    pub const fn new(bus: B, address: u8) -> Self {
+        Self { bus, address }
+    }
+
+    fn read_word(&mut self, register: u8) -> Result<u16, ReadingError> {
+        let mut word = [0; 2];
+        self.bus.write_read(
+            self.address,
+            &[Self::COMMAND | Self::WORD | register],
+            &mut word,
+        )?;
+        Ok(u16::from_le_bytes(word))
+    }
+}
+
+/// Illuminance from the broadband and infrared counts of a TSL2561 in the T, FN or CL package,
+/// with the formula of the datasheet.
+#[must_use]
+pub fn tsl2561_lux(broadband: f32, infrared: f32) -> f32 {
+    if broadband <= 0.0 {
+        return 0.0;
+    }
+    let ratio = infrared / broadband;
+    let lux = if ratio <= 0.5 {
+        0.0304f32.mul_add(broadband, -0.062 * broadband * ratio.powf(1.4))
+    } else if ratio <= 0.61 {
+        0.0224f32.mul_add(broadband, -0.031 * infrared)
+    } else if ratio <= 0.8 {
+        0.0128f32.mul_add(broadband, -0.0153 * infrared)
+    } else if ratio <= 1.3 {
+        0.00146f32.mul_add(broadband, -0.00112 * infrared)
+    } else {
+        0.0
+    };
+    lux.max(0.0)
+}
+
+impl<B: I2cBus> LuxSensor for Tsl2561<B> {
+    fn start(&mut self) -> Result<(), ReadingError> {
+        self.bus.write(
+            self.address,
+            &[Self::COMMAND | Self::CONTROL, Self::POWER_ON],
+        )?;
+        self.bus.write(
+            self.address,
+            &[Self::COMMAND | Self::TIMING, Self::LOW_GAIN_402MS],
+        )
+    }
+
+    /// The sensor integrates continuously once powered, so a full integration is needed.
+    fn conversion_time(&self) -> Duration {
+        Duration::from_millis(450)
+    }
+
+    fn fetch(&mut self) -> Result<LightReading, ReadingError> {
+        let broadband = self.read_word(Self::DATA0)?;
+        let infrared = self.read_word(Self::DATA1)?;
+        if broadband == u16::MAX || infrared == u16::MAX {
+            return Err(ReadingError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "TSL2561 is saturated",
+            )));
+        }
+        Ok(LightReading {
+            lux: tsl2561_lux(
+                f32::from(broadband) * Self::GAIN_SCALE,
+                f32::from(infrared) * Self::GAIN_SCALE,
+            ),
+        })
+    }
+}
+
+impl<B: I2cBus + Send + 'static> BlockingSensor for Tsl2561<B> {
+    fn name(&self) -> &'static str {
+        "TSL2561"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Illuminance]
+    }
+
+    fn read_blocking(&mut self) -> Result<Measurements, ReadingError> {
+        Ok(Measurement::Illuminance(LuxSensor::read(self)?.lux).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tsl2561_lux, Bh1750, LuxSensor, Tsl2561};
+    use crate::{
+        bus::mock::MockBus,
+        sensor::{Blocking, Quantity, Sensor},
+    };
+
+    #[test]
+    fn bh1750() {
+        // Example of the datasheet.
+        let mut sensor = Bh1750::new(MockBus::new(&[&[0x83, 0x90]]), 0x23);
+        sensor.start().unwrap();
+        let reading = sensor.fetch().unwrap();
+        assert!((reading.lux - 28066.667).abs() < 0.01);
+        assert_eq!(sensor.bus.writes, [[0x01], [0x20]]);
+        assert_eq!(sensor.bus.addresses, [0x23; 3]);
+    }
+
+    #[tokio::test]
+    async fn sensor() {
+        let mut sensor = Blocking::new(Bh1750::new(MockBus::new(&[&[0x00, 0x78]]), 0x23));
+        let measurements = sensor.read().await.unwrap();
+        let lux = measurements.value(Quantity::Illuminance).unwrap();
+        assert!((lux - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn tsl2561() {
+        // 1000 broadband and 200 infrared counts, little-endian.
+        let mut sensor = Tsl2561::new(MockBus::new(&[&[0xe8, 0x03], &[0xc8, 0x00]]), 0x39);
+        sensor.start().unwrap();
+        let reading = sensor.fetch().unwrap();
+        assert!((reading.lux - 382.2).abs() < 0.5, "{}", reading.lux);
+        assert_eq!(
+            sensor.bus.writes,
+            [vec![0x80, 0x03], vec![0x81, 0x02], vec![0xac], vec![0xae]]
+        );
+
+        let mut sensor = Tsl2561::new(MockBus::new(&[&[0xff, 0xff], &[0x00, 0x10]]), 0x39);
+        assert!(sensor.fetch().is_err());
+    }
+
+    #[test]
+    fn tsl2561_ratio() {
+        assert!(tsl2561_lux(0.0, 0.0).abs() < f32::EPSILON);
+        // Mostly infrared, such as an incandescent bulb behind a filter.
+        assert!(tsl2561_lux(1000.0, 1400.0).abs() < f32::EPSILON);
+        assert!((tsl2561_lux(1000.0, 700.0) - 2.09).abs() < 0.01);
+    }
+}
@@ -0,0 +1,403 @@
+//! Counters and gauges exposed in the Prometheus text format.
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use crate::sensor::{Measurement, Measurements, SensorError};
+
+/// Upper bounds, in seconds, of the read latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 0.6, 0.75, 1.0, 2.5, 5.0,
+];
+
+/// Values of [`SensorError::kind`].
+const ERROR_KINDS: [&str; 6] = ["timeout", "checksum", "gpio", "io", "realtime", "too_soon"];
+
+/// A gauge holding a `f64`, unset until the first value is recorded.
+#[derive(Debug)]
+struct Gauge(AtomicU64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Self(AtomicU64::new(f64::NAN.to_bits()))
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<f64> {
+        Some(f64::from_bits(self.0.load(Ordering::Relaxed))).filter(|value| !value.is_nan())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn render(&self, out: &mut String) {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let _ = writeln!(
+            out,
+            "# HELP rpi_read_duration_seconds Duration of sensor reads.\n\
+             # TYPE rpi_read_duration_seconds histogram"
+        );
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "rpi_read_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+                load(bucket)
+            );
+        }
+        let count = load(&self.count);
+        let _ = writeln!(
+            out,
+            "rpi_read_duration_seconds_bucket{{le=\"+Inf\"}} {count}\n\
+             rpi_read_duration_seconds_sum {}\n\
+             rpi_read_duration_seconds_count {count}",
+            Duration::from_micros(load(&self.sum_micros)).as_secs_f64()
+        );
+    }
+}
+
+/// Operational metrics of a service.
+#[derive(Debug)]
+pub struct Metrics {
+    temperature: Gauge,
+    humidity: Gauge,
+    pressure: Gauge,
+    light: Gauge,
+    light_level: Gauge,
+    /// Last temperature of each 1-Wire probe, by ROM id.
+    probes: Mutex<BTreeMap<String, f64>>,
+    read_attempts: AtomicU64,
+    /// Failed reads, indexed like [`ERROR_KINDS`].
+    errors: [AtomicU64; ERROR_KINDS.len()],
+    /// Attempts and failures of the captures in normal and in real-time mode.
+    normal_mode: [AtomicU64; 2],
+    realtime_mode: [AtomicU64; 2],
+    publish_successes: AtomicU64,
+    publish_failures: AtomicU64,
+    reconnects: AtomicU64,
+    read_latency: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            temperature: Gauge::new(),
+            humidity: Gauge::new(),
+            pressure: Gauge::new(),
+            light: Gauge::new(),
+            light_level: Gauge::new(),
+            probes: Mutex::new(BTreeMap::new()),
+            read_attempts: AtomicU64::new(0),
+            errors: Default::default(),
+            normal_mode: Default::default(),
+            realtime_mode: Default::default(),
+            publish_successes: AtomicU64::new(0),
+            publish_failures: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            read_latency: Histogram::default(),
+        }
+    }
+
+    /// Record a read attempt that took `latency` and failed with `error`, if any. A read made
+    /// too soon counts as a failure, the sensor wasn't read.
+    pub fn read(&self, latency: Duration, error: Option<&SensorError>) {
+        self.read_attempts.fetch_add(1, Ordering::Relaxed);
+        self.read_latency.observe(latency);
+        if let Some(error) = error {
+            if let Some(index) = ERROR_KINDS.iter().position(|&kind| kind == error.kind()) {
+                self.errors[index].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Record the outcome of a capture made in real-time mode or not, to compare their failure
+    /// rates.
+    pub fn capture_mode(&self, realtime: bool, success: bool) {
+        let [attempts, failures] = if realtime {
+            &self.realtime_mode
+        } else {
+            &self.normal_mode
+        };
+        attempts.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn temperature(&self, celsius: f32) {
+        self.temperature.set(f64::from(celsius));
+    }
+
+    pub fn humidity(&self, percent: f32) {
+        self.humidity.set(f64::from(percent));
+    }
+
+    pub fn pressure(&self, hectopascals: f32) {
+        self.pressure.set(f64::from(hectopascals));
+    }
+
+    pub fn probe_temperature(&self, id: &str, celsius: f32) {
+        self.probes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id.to_string(), f64::from(celsius));
+    }
+
+    pub fn light(&self, light: bool) {
+        self.light.set(if light { 1.0 } else { 0.0 });
+    }
+
+    pub fn light_level(&self, level: f32) {
+        self.light_level.set(f64::from(level));
+    }
+
+    /// Set the gauges of the values of a reading.
+    pub fn measurements(&self, measurements: &Measurements) {
+        for measurement in measurements.iter() {
+            match measurement {
+                Measurement::Temperature(celsius) => self.temperature(celsius),
+                Measurement::Humidity(percent) => self.humidity(percent),
+                Measurement::Pressure(hectopascals) => self.pressure(hectopascals),
+                Measurement::Illuminance(level) | Measurement::LightLevel(level) => {
+                    self.light_level(level);
+                }
+                Measurement::Light(light) => self.light(light),
+            }
+        }
+    }
+
+    pub fn published(&self, success: bool) {
+        let counter = if success {
+            &self.publish_successes
+        } else {
+            &self.publish_failures
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render_gauges(&self, out: &mut String) {
+        for (name, help, gauge) in [
+            (
+                "rpi_temperature_celsius",
+                "Last temperature read.",
+                &self.temperature,
+            ),
+            (
+                "rpi_humidity_percent",
+                "Last humidity read.",
+                &self.humidity,
+            ),
+            (
+                "rpi_pressure_hectopascals",
+                "Last pressure read.",
+                &self.pressure,
+            ),
+            (
+                "rpi_light",
+                "State of the binary input, such as light, at the last read.",
+                &self.light,
+            ),
+            (
+                "rpi_light_level",
+                "Last light level read, in the configured unit.",
+                &self.light_level,
+            ),
+        ] {
+            if let Some(value) = gauge.get() {
+                let _ = writeln!(
+                    out,
+                    "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+                );
+            }
+        }
+
+        let probes = self.probes.lock().unwrap_or_else(PoisonError::into_inner);
+        if !probes.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP rpi_probe_temperature_celsius Last temperature read by each probe.\n\
+                 # TYPE rpi_probe_temperature_celsius gauge"
+            );
+            for (id, celsius) in probes.iter() {
+                let _ = writeln!(
+                    out,
+                    "rpi_probe_temperature_celsius{{rom_id=\"{id}\"}} {celsius}"
+                );
+            }
+        }
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.render_gauges(&mut out);
+
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let _ = writeln!(
+            out,
+            "# HELP rpi_read_attempts_total Sensor read attempts.\n\
+             # TYPE rpi_read_attempts_total counter\n\
+             rpi_read_attempts_total {}",
+            load(&self.read_attempts)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP rpi_read_errors_total Failed sensor reads by kind.\n\
+             # TYPE rpi_read_errors_total counter"
+        );
+        for (kind, counter) in ERROR_KINDS.iter().zip(&self.errors) {
+            let _ = writeln!(
+                out,
+                "rpi_read_errors_total{{kind=\"{kind}\"}} {}",
+                load(counter)
+            );
+        }
+        if load(&self.normal_mode[0]) + load(&self.realtime_mode[0]) > 0 {
+            let _ = writeln!(
+                out,
+                "# HELP rpi_capture_attempts_total Captures by scheduling mode.\n\
+                 # TYPE rpi_capture_attempts_total counter\n\
+                 rpi_capture_attempts_total{{mode=\"normal\"}} {}\n\
+                 rpi_capture_attempts_total{{mode=\"realtime\"}} {}\n\
+                 # HELP rpi_capture_failures_total Failed captures by scheduling mode.\n\
+                 # TYPE rpi_capture_failures_total counter\n\
+                 rpi_capture_failures_total{{mode=\"normal\"}} {}\n\
+                 rpi_capture_failures_total{{mode=\"realtime\"}} {}",
+                load(&self.normal_mode[0]),
+                load(&self.realtime_mode[0]),
+                load(&self.normal_mode[1]),
+                load(&self.realtime_mode[1])
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP rpi_publish_total MQTT publications by result.\n\
+             # TYPE rpi_publish_total counter\n\
+             rpi_publish_total{{result=\"success\"}} {}\n\
+             rpi_publish_total{{result=\"failure\"}} {}",
+            load(&self.publish_successes),
+            load(&self.publish_failures)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP rpi_reconnects_total Reconnections to the MQTT broker.\n\
+             # TYPE rpi_reconnects_total counter\n\
+             rpi_reconnects_total {}",
+            load(&self.reconnects)
+        );
+
+        self.read_latency.render(&mut out);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::{
+        sensor::{Measurement, SensorError},
+        ReadingError,
+    };
+
+    use std::time::Duration;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        assert!(!metrics.render().contains("rpi_temperature_celsius"));
+
+        metrics.read(Duration::from_millis(530), None);
+        metrics.read(
+            Duration::from_millis(540),
+            Some(&SensorError::from(ReadingError::Checksum {
+                pulses: Vec::new(),
+                data: [0; 5],
+                expected: 0,
+                actual: 1,
+            })),
+        );
+        metrics.read(
+            Duration::ZERO,
+            Some(&SensorError::TooSoon(Duration::from_secs(1))),
+        );
+        metrics.temperature(21.5);
+        metrics.measurements(&Measurement::Humidity(45.0).into());
+        metrics.probe_temperature("28-0316a2797fff", -18.5);
+        metrics.published(true);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("rpi_temperature_celsius 21.5\n"));
+        assert!(rendered.contains("rpi_humidity_percent 45\n"));
+        assert!(!rendered.contains("rpi_light "));
+        assert!(
+            rendered.contains("rpi_probe_temperature_celsius{rom_id=\"28-0316a2797fff\"} -18.5\n")
+        );
+        assert!(rendered.contains("rpi_read_attempts_total 3\n"));
+        assert!(rendered.contains("rpi_read_errors_total{kind=\"checksum\"} 1\n"));
+        assert!(rendered.contains("rpi_read_errors_total{kind=\"too_soon\"} 1\n"));
+        assert!(rendered.contains("rpi_read_errors_total{kind=\"timeout\"} 0\n"));
+        assert!(rendered.contains("rpi_publish_total{result=\"success\"} 1\n"));
+        assert!(!rendered.contains("rpi_capture_attempts_total"));
+        assert!(rendered.contains("rpi_read_duration_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(rendered.contains("rpi_read_duration_seconds_bucket{le=\"0.6\"} 3\n"));
+        assert!(rendered.contains("rpi_read_duration_seconds_count 3\n"));
+        assert!(rendered.contains("rpi_read_duration_seconds_sum 1.07\n"));
+    }
+
+    #[test]
+    fn capture_modes() {
+        let metrics = Metrics::new();
+        metrics.capture_mode(false, false);
+        metrics.capture_mode(false, true);
+        metrics.capture_mode(true, true);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("rpi_capture_attempts_total{mode=\"normal\"} 2\n"));
+        assert!(rendered.contains("rpi_capture_attempts_total{mode=\"realtime\"} 1\n"));
+        assert!(rendered.contains("rpi_capture_failures_total{mode=\"normal\"} 1\n"));
+        assert!(rendered.contains("rpi_capture_failures_total{mode=\"realtime\"} 0\n"));
+    }
+}
@@ -0,0 +1,236 @@
+//! DS18B20 temperature probes on the 1-Wire bus, read through the `w1_therm` kernel driver.
+//!
+//! Enable the bus with `dtoverlay=w1-gpio` in `/boot/firmware/config.txt`; each probe then shows
+//! up as a `28-<serial>` device.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    sensor::{BoxFuture, Measurement, Measurements, Quantity, Sensor, SensorError},
+    ReadingError,
+};
+
+/// Where the kernel lists the 1-Wire devices.
+pub const W1_DEVICES: &str = "/sys/bus/w1/devices";
+
+/// Family code of the DS18B20, prefixing the ROM id of the probes.
+const FAMILY: &str = "28-";
+/// Temperature in thousandths of a degree, on recent kernels which check the CRC themselves.
+const TEMPERATURE: &str = "temperature";
+/// Scratchpad dump with the CRC verdict of the driver and the temperature.
+const W1_SLAVE: &str = "w1_slave";
+/// Value of the temperature register until a conversion completes, read when the probe lost
+/// power during the conversion.
+const POWER_ON_RESET: i32 = 85_000;
+
+/// CRC-8 of the 1-Wire devices: polynomial 0x31, reflected, initialized to 0.
+#[must_use]
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x01 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ 0x8c
+            }
+        })
+    })
+}
+
+fn invalid(message: String) -> ReadingError {
+    ReadingError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Temperature in thousandths of a degree from the contents of `w1_slave`:
+///
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+fn parse_w1_slave(contents: &str) -> Result<i32, ReadingError> {
+    let mut lines = contents.lines();
+    let (Some(crc_line), Some(temperature_line)) = (lines.next(), lines.next()) else {
+        return Err(invalid(format!("truncated {W1_SLAVE}: {contents:?}")));
+    };
+
+    let (scratchpad, verdict) = crc_line
+        .split_once(':')
+        .ok_or_else(|| invalid(format!("malformed {W1_SLAVE} line: {crc_line:?}")))?;
+    let scratchpad = scratchpad
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()
+        .filter(|scratchpad| scratchpad.len() == 9)
+        .ok_or_else(|| invalid(format!("malformed scratchpad: {scratchpad:?}")))?;
+    let expected = crc8(&scratchpad[..8]);
+    if expected != scratchpad[8] || !verdict.trim_end().ends_with("YES") {
+        return Err(invalid(format!(
+            "CRC mismatch: expected {expected:#04x}, got {:#04x}",
+            scratchpad[8]
+        )));
+    }
+
+    temperature_line
+        .rsplit_once("t=")
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .ok_or_else(|| invalid(format!("malformed {W1_SLAVE} line: {temperature_line:?}")))
+}
+
+/// A DS18B20 probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ds18b20 {
+    device: PathBuf,
+}
+
+impl Ds18b20 {
+    /// The probe at `device`, such as `/sys/bus/w1/devices/28-0316a2797fff`.
+    pub fn new(device: impl Into<PathBuf>) -> Self {
+        Self {
+            device: device.into(),
+        }
+    }
+
+    /// Every probe in `devices`, usually [`W1_DEVICES`], sorted by ROM id.
+    ///
+    /// # Errors
+    /// Returns an error if `devices` can't be listed, when the overlay isn't loaded.
+    pub fn probes(devices: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let mut probes = fs::read_dir(devices.as_ref())?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(FAMILY))
+            .map(|entry| Self::new(entry.path()))
+            .collect::<Vec<_>>();
+        probes.sort_by(|a, b| a.device.cmp(&b.device));
+        Ok(probes)
+    }
+
+    /// The ROM id of the probe, such as `28-0316a2797fff`.
+    #[must_use]
+    pub fn id(&self) -> String {
+        self.device
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+
+    /// Read the temperature, blocking for the 750 ms of the conversion.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the probe can't be read, its CRC is wrong, or it returned
+    /// the power-on reset value.
+    pub fn read(&self) -> Result<f32, ReadingError> {
+        let millis = match fs::read_to_string(self.device.join(TEMPERATURE)) {
+            Ok(contents) => contents.trim().parse::<i32>().map_err(|e| {
+                invalid(format!("invalid {TEMPERATURE} {:?}: {e}", contents.trim()))
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let contents =
+                    fs::read_to_string(self.device.join(W1_SLAVE)).map_err(ReadingError::Io)?;
+                parse_w1_slave(&contents)?
+            }
+            Err(e) => return Err(ReadingError::Io(e)),
+        };
+        if millis == POWER_ON_RESET {
+            return Err(invalid(format!(
+                "{} returned its power-on value, is it powered?",
+                self.id()
+            )));
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let millis = millis as f32;
+        Ok(millis / 1000.0)
+    }
+
+    /// Asynchronous version of [`Ds18b20::read`], running on the blocking pool.
+    ///
+    /// # Errors
+    /// See [`Ds18b20::read`].
+    pub async fn read_async(&self) -> Result<f32, ReadingError> {
+        let probe = self.clone();
+        crate::dht22::run_blocking(move || probe.read()).await
+    }
+}
+
+impl Sensor for Ds18b20 {
+    fn name(&self) -> &'static str {
+        "DS18B20"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Temperature]
+    }
+
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        Box::pin(async move {
+            let temperature = self.read_async().await?;
+            Ok(Measurement::Temperature(temperature).into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc8, parse_w1_slave, Ds18b20};
+    use crate::{temp_dir::TempDir, ReadingError};
+
+    use std::{fs, path::Path};
+
+    const ROOM: &str = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+                        72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+
+    fn probe(root: &Path, id: &str, file: &str, contents: &str) {
+        let device = root.join(id);
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join(file), contents).unwrap();
+    }
+
+    #[test]
+    fn w1_slave() {
+        assert_eq!(
+            crc8(&[0x72, 0x01, 0x4b, 0x46, 0x7f, 0xff, 0x0e, 0x10]),
+            0x57
+        );
+        assert_eq!(parse_w1_slave(ROOM).unwrap(), 23125);
+
+        let corrupted = ROOM.replacen("0e 10 57", "0e 11 57", 1);
+        assert!(matches!(
+            parse_w1_slave(&corrupted),
+            Err(ReadingError::Io(e)) if e.to_string().contains("CRC")
+        ));
+        assert!(parse_w1_slave(&ROOM.replace("YES", "NO")).is_err());
+        assert!(parse_w1_slave("72 01 4b").is_err());
+    }
+
+    #[test]
+    fn probes() {
+        let dir = TempDir::new();
+        let root = dir.path();
+        probe(root, "28-0316a2797fff", "w1_slave", ROOM);
+        probe(
+            root,
+            "28-01193a5c2b11",
+            "w1_slave",
+            "d8 fe 4b 46 7f ff 08 10 6d : crc=6d YES\n\
+             d8 fe 4b 46 7f ff 08 10 6d t=-18500\n",
+        );
+        probe(root, "28-0416b0c4d2ff", "temperature", "85000\n");
+        // The bus master isn't a probe.
+        fs::create_dir_all(root.join("w1_bus_master1")).unwrap();
+
+        let probes = Ds18b20::probes(root).unwrap();
+        let ids = probes.iter().map(Ds18b20::id).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            ["28-01193a5c2b11", "28-0316a2797fff", "28-0416b0c4d2ff"]
+        );
+        assert!((probes[0].read().unwrap() + 18.5).abs() < f32::EPSILON);
+        assert!((probes[1].read().unwrap() - 23.125).abs() < f32::EPSILON);
+        assert!(probes[2].read().is_err());
+
+        let root = root.to_path_buf();
+        drop(dir);
+        assert!(Ds18b20::probes(&root).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Real-time scheduling for the timing-critical parts of a reading.
+//!
+//! Most bit-banging errors come from the capture thread being preempted in the middle of a busy
+//! loop. While a [`RealtimeGuard`] is alive, the calling thread runs with the `SCHED_FIFO`
+//! policy, optionally pinned to a single core. The memory of the process is locked once, with
+//! [`lock_memory`], so that page faults can't delay it either.
+use std::{io, mem};
+
+use tracing::warn;
+
+use crate::ReadingError;
+
+/// Settings of the real-time mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RealtimeOptions {
+    /// `SCHED_FIFO` priority, from 1 to 99.
+    pub priority: i32,
+
+    /// Core to pin the thread to, ideally one isolated with `isolcpus`.
+    pub cpu: Option<usize>,
+}
+
+impl Default for RealtimeOptions {
+    fn default() -> Self {
+        Self {
+            priority: 50,
+            cpu: None,
+        }
+    }
+}
+
+/// Restores the scheduling policy and the CPU affinity of the thread when dropped.
+pub struct RealtimeGuard {
+    policy: Option<(libc::c_int, libc::sched_param)>,
+    affinity: Option<libc::cpu_set_t>,
+}
+
+fn check(step: &str, result: libc::c_int) -> Result<(), ReadingError> {
+    if result == -1 {
+        let e = io::Error::last_os_error();
+        return Err(ReadingError::Realtime(io::Error::new(
+            e.kind(),
+            format!("{step}: {e}"),
+        )));
+    }
+    Ok(())
+}
+
+/// Lock the current and future memory of the process with `mlockall`, until it exits.
+///
+/// # Errors
+/// Returns `ReadingError::Realtime` if the memory can't be locked, typically because the process
+/// lacks the `CAP_IPC_LOCK` capability.
+pub fn lock_memory() -> Result<(), ReadingError> {
+    // SAFETY: `mlockall` only takes flags.
+    check("mlockall", unsafe {
+        libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE)
+    })
+}
+
+/// Switch the calling thread to real-time scheduling until the returned guard is dropped.
+///
+/// # Errors
+/// Returns `ReadingError::Realtime` if a setting can't be applied, typically because the process
+/// lacks the `CAP_SYS_NICE` capability. The settings already applied are reverted.
+pub fn enter(options: &RealtimeOptions) -> Result<RealtimeGuard, ReadingError> {
+    let mut guard = RealtimeGuard {
+        policy: None,
+        affinity: None,
+    };
+
+    // SAFETY: the pointers passed to libc point to initialized values living on this stack frame.
+    unsafe {
+        let mut previous_param: libc::sched_param = mem::zeroed();
+        let previous_policy = libc::sched_getscheduler(0);
+        check("sched_getscheduler", previous_policy)?;
+        check(
+            "sched_getparam",
+            libc::sched_getparam(0, &raw mut previous_param),
+        )?;
+
+        let param = libc::sched_param {
+            sched_priority: options.priority,
+        };
+        check(
+            "sched_setscheduler",
+            libc::sched_setscheduler(0, libc::SCHED_FIFO, &raw const param),
+        )?;
+        guard.policy = Some((previous_policy, previous_param));
+
+        if let Some(cpu) = options.cpu {
+            let mut previous_set: libc::cpu_set_t = mem::zeroed();
+            check(
+                "sched_getaffinity",
+                libc::sched_getaffinity(
+                    0,
+                    mem::size_of::<libc::cpu_set_t>(),
+                    &raw mut previous_set,
+                ),
+            )?;
+
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            libc::CPU_ZERO(&mut set);
+            libc::CPU_SET(cpu, &mut set);
+            check(
+                "sched_setaffinity",
+                libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &raw const set),
+            )?;
+            guard.affinity = Some(previous_set);
+        }
+    }
+
+    Ok(guard)
+}
+
+impl Drop for RealtimeGuard {
+    fn drop(&mut self) {
+        // The thread goes back to the blocking pool, in real-time mode if restoring fails.
+        // SAFETY: the saved values come from the matching getters in `enter`.
+        unsafe {
+            if let Some(set) = &self.affinity {
+                let result = libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), set);
+                if let Err(e) = check("sched_setaffinity", result) {
+                    warn!("Failed to restore the CPU affinity: {e}");
+                }
+            }
+            if let Some((policy, param)) = &self.policy {
+                if let Err(e) = check(
+                    "sched_setscheduler",
+                    libc::sched_setscheduler(0, *policy, param),
+                ) {
+                    warn!("Failed to restore the scheduling policy: {e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enter, RealtimeOptions};
+    use crate::ReadingError;
+
+    use std::io::ErrorKind;
+
+    #[test]
+    fn restores_the_scheduling_policy() {
+        // SAFETY: querying the policy of the calling thread has no side effect.
+        let policy = || unsafe { libc::sched_getscheduler(0) };
+        let before = policy();
+
+        match enter(&RealtimeOptions {
+            cpu: Some(0),
+            ..Default::default()
+        }) {
+            Ok(guard) => {
+                assert_eq!(policy(), libc::SCHED_FIFO);
+                drop(guard);
+                assert_eq!(policy(), before);
+            }
+            // Without privileges, nothing must have changed.
+            Err(ReadingError::Realtime(e)) => {
+                assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+                assert_eq!(policy(), before);
+            }
+            Err(e) => panic!("unexpected error {e}"),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Recording of raw DHT22 captures, one JSON object per line, so that field captures can be
+//! replayed and analyzed later.
+use serde_json::{json, Value};
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{dht22::Pulses, ReadingError};
+
+/// A raw capture and how it was classified when it was recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub pin: u8,
+    pub pulses: Vec<usize>,
+    /// `None` for a successful reading, the error message otherwise.
+    pub error: Option<String>,
+}
+
+impl Capture {
+    /// The capture behind `result`, `None` if nothing was captured.
+    #[must_use]
+    pub fn new(pin: u8, result: &Result<Pulses, ReadingError>) -> Option<Self> {
+        let (pulses, error) = match result {
+            Ok(pulses) => (pulses.to_vec(), None),
+            Err(
+                e @ (ReadingError::Timeout { pulses, .. } | ReadingError::Checksum { pulses, .. }),
+            ) => (pulses.clone(), Some(e.to_string())),
+            Err(ReadingError::Gpio(_) | ReadingError::Io(_) | ReadingError::Realtime(_)) => {
+                return None
+            }
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| {
+                u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+            });
+
+        Some(Self {
+            timestamp,
+            pin,
+            pulses,
+            error,
+        })
+    }
+
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp,
+            "pin": self.pin,
+            "pulses": self.pulses,
+            "error": self.error,
+        })
+    }
+
+    /// # Errors
+    /// Returns a message if `value` isn't a capture.
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let field = |name: &str| value.get(name).ok_or_else(|| format!("missing {name}"));
+        let pulses = field("pulses")?
+            .as_array()
+            .ok_or("pulses isn't an array")?
+            .iter()
+            .map(|pulse| {
+                pulse
+                    .as_u64()
+                    .and_then(|pulse| usize::try_from(pulse).ok())
+                    .ok_or_else(|| format!("invalid pulse {pulse}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            timestamp: field("timestamp")?
+                .as_u64()
+                .ok_or("timestamp isn't a number")?,
+            pin: field("pin")?
+                .as_u64()
+                .and_then(|pin| u8::try_from(pin).ok())
+                .ok_or("pin isn't a valid u8")?,
+            pulses,
+            error: value
+                .get("error")
+                .and_then(Value::as_str)
+                .map(ToString::to_string),
+        })
+    }
+}
+
+/// Appends captures to a file.
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// # Errors
+    /// Returns the error if the file can't be opened.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// # Errors
+    /// Returns the error if the capture can't be written.
+    pub fn record(&self, capture: &Capture) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| io::Error::other("recorder lock poisoned"))?;
+        writeln!(file, "{}", capture.to_json())?;
+        file.flush()
+    }
+}
+
+/// Read the captures saved by a [`Recorder`].
+///
+/// # Errors
+/// Returns the error if the file can't be read or a line isn't a capture.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Capture>> {
+    let mut captures = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {e}", number + 1),
+            )
+        };
+        let value = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+        captures.push(Capture::from_json(&value).map_err(invalid)?);
+    }
+    Ok(captures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, Capture, Recorder};
+    use crate::{ReadingError, TimeoutPhase};
+
+    use std::{env, fs, process};
+
+    #[test]
+    fn record_and_load() {
+        let path = env::temp_dir().join(format!("rpi-gpio-captures-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let success = Capture::new(4, &Ok([1; 82])).unwrap();
+        let timeout = Capture::new(
+            4,
+            &Err(ReadingError::Timeout {
+                phase: TimeoutPhase::BitHigh(0),
+                pulses: vec![80, 80, 50, 26],
+            }),
+        )
+        .unwrap();
+
+        let recorder = Recorder::open(&path).unwrap();
+        recorder.record(&success).unwrap();
+        recorder.record(&timeout).unwrap();
+
+        let captures = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(captures, [success, timeout]);
+        assert_eq!(captures[0].error, None);
+        assert_eq!(
+            captures[1].error.as_deref(),
+            Some("timed out during bit 0 high after 2 pulses")
+        );
+    }
+}
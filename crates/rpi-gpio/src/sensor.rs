@@ -0,0 +1,359 @@
+//! A common interface over the sensors, so that publishing, discovery and metrics are written
+//! once instead of for each model.
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{
+    dht22::{run_blocking, Reading},
+    ReadingError,
+};
+
+/// A future returned by a [`Sensor`], boxed so that the sensor can be picked at runtime.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What a value measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quantity {
+    Temperature,
+    Humidity,
+    Pressure,
+    Illuminance,
+    /// Light level as a percentage of the full scale of a converter.
+    LightLevel,
+    /// Whether there's light.
+    Light,
+}
+
+impl Quantity {
+    /// Key of the value in the JSON payload.
+    #[must_use]
+    pub const fn key(self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+            Self::Pressure => "pressure",
+            Self::Illuminance => "lux",
+            Self::LightLevel => "light_percent",
+            Self::Light => "light",
+        }
+    }
+
+    /// Unit of the value, `None` for a state.
+    #[must_use]
+    pub const fn unit(self) -> Option<&'static str> {
+        match self {
+            Self::Temperature => Some("°C"),
+            Self::Humidity | Self::LightLevel => Some("%"),
+            Self::Pressure => Some("hPa"),
+            Self::Illuminance => Some("lx"),
+            Self::Light => None,
+        }
+    }
+
+    /// Home Assistant device class.
+    #[must_use]
+    pub const fn device_class(self) -> Option<&'static str> {
+        match self {
+            Self::Temperature => Some("temperature"),
+            Self::Humidity => Some("humidity"),
+            Self::Pressure => Some("atmospheric_pressure"),
+            Self::Illuminance => Some("illuminance"),
+            Self::LightLevel => None,
+            Self::Light => Some("light"),
+        }
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = String;
+
+    /// Parse the key of a quantity in the JSON payload.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature" => Ok(Self::Temperature),
+            "humidity" => Ok(Self::Humidity),
+            "pressure" => Ok(Self::Pressure),
+            "lux" => Ok(Self::Illuminance),
+            "light_percent" => Ok(Self::LightLevel),
+            "light" => Ok(Self::Light),
+            _ => Err(format!("unknown quantity {s}")),
+        }
+    }
+}
+
+/// A value read from a sensor, in the unit of its [`Quantity`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Measurement {
+    Temperature(f32),
+    Humidity(f32),
+    Pressure(f32),
+    Illuminance(f32),
+    LightLevel(f32),
+    Light(bool),
+}
+
+impl Measurement {
+    #[must_use]
+    pub const fn quantity(self) -> Quantity {
+        match self {
+            Self::Temperature(_) => Quantity::Temperature,
+            Self::Humidity(_) => Quantity::Humidity,
+            Self::Pressure(_) => Quantity::Pressure,
+            Self::Illuminance(_) => Quantity::Illuminance,
+            Self::LightLevel(_) => Quantity::LightLevel,
+            Self::Light(_) => Quantity::Light,
+        }
+    }
+
+    /// The value, if it's a number rather than a state.
+    #[must_use]
+    pub const fn number(self) -> Option<f32> {
+        match self {
+            Self::Temperature(value)
+            | Self::Humidity(value)
+            | Self::Pressure(value)
+            | Self::Illuminance(value)
+            | Self::LightLevel(value) => Some(value),
+            Self::Light(_) => None,
+        }
+    }
+
+    /// The same quantity with `value`, unchanged if it's a state.
+    #[must_use]
+    pub const fn with_number(self, value: f32) -> Self {
+        match self {
+            Self::Temperature(_) => Self::Temperature(value),
+            Self::Humidity(_) => Self::Humidity(value),
+            Self::Pressure(_) => Self::Pressure(value),
+            Self::Illuminance(_) => Self::Illuminance(value),
+            Self::LightLevel(_) => Self::LightLevel(value),
+            Self::Light(_) => self,
+        }
+    }
+}
+
+/// The values of a single reading.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Measurements(Vec<Measurement>);
+
+impl Measurements {
+    pub fn iter(&self) -> impl Iterator<Item = Measurement> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// The measurement of `quantity`, if the reading has one.
+    #[must_use]
+    pub fn get(&self, quantity: Quantity) -> Option<Measurement> {
+        self.iter()
+            .find(|measurement| measurement.quantity() == quantity)
+    }
+
+    /// The value of `quantity`, if the reading has one and it's a number.
+    #[must_use]
+    pub fn value(&self, quantity: Quantity) -> Option<f32> {
+        self.get(quantity)?.number()
+    }
+}
+
+impl FromIterator<Measurement> for Measurements {
+    fn from_iter<I: IntoIterator<Item = Measurement>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl From<Measurement> for Measurements {
+    fn from(measurement: Measurement) -> Self {
+        Self(vec![measurement])
+    }
+}
+
+impl From<Reading> for Measurements {
+    fn from(reading: Reading) -> Self {
+        let mut measurements = vec![
+            Measurement::Temperature(reading.temperature),
+            Measurement::Humidity(reading.humidity),
+        ];
+        measurements.extend(reading.pressure.map(Measurement::Pressure));
+        Self(measurements)
+    }
+}
+
+/// Errors of [`Sensor::read`].
+#[derive(Debug)]
+pub enum SensorError {
+    /// Occurs if the sensor can't be read.
+    Reading(ReadingError),
+
+    /// Occurs if the sensor is read again before its minimum interval elapsed, with the time
+    /// left to wait.
+    TooSoon(Duration),
+}
+
+impl SensorError {
+    /// A short name for the kind of error.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Reading(e) => e.kind(),
+            Self::TooSoon(_) => "too_soon",
+        }
+    }
+
+    /// The error of the driver, if the sensor was read.
+    #[must_use]
+    pub const fn reading(&self) -> Option<&ReadingError> {
+        match self {
+            Self::Reading(e) => Some(e),
+            Self::TooSoon(_) => None,
+        }
+    }
+}
+
+impl From<ReadingError> for SensorError {
+    fn from(err: ReadingError) -> Self {
+        Self::Reading(err)
+    }
+}
+
+impl fmt::Display for SensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reading(e) => e.fmt(f),
+            Self::TooSoon(remaining) => {
+                write!(f, "read too soon, wait {} ms more", remaining.as_millis())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SensorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Reading(e) => Some(e),
+            Self::TooSoon(_) => None,
+        }
+    }
+}
+
+/// A source of measurements.
+pub trait Sensor {
+    /// Model of the sensor, such as `DHT22`.
+    fn name(&self) -> &'static str;
+
+    /// What the readings contain.
+    fn quantities(&self) -> &'static [Quantity];
+
+    /// Shortest interval between two readings the sensor supports.
+    fn min_interval(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Read the sensor without blocking the runtime.
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>>;
+}
+
+/// A sensor whose driver blocks the thread, on its bus and while it converts, made a [`Sensor`]
+/// by [`Blocking`].
+pub trait BlockingSensor: Send + 'static {
+    /// See [`Sensor::name`].
+    fn name(&self) -> &'static str;
+
+    /// See [`Sensor::quantities`].
+    fn quantities(&self) -> &'static [Quantity];
+
+    /// Read the sensor, blocking until the conversion completes.
+    ///
+    /// # Errors
+    /// Returns a `ReadingError` if the sensor doesn't answer.
+    fn read_blocking(&mut self) -> Result<Measurements, ReadingError>;
+}
+
+/// A [`BlockingSensor`] read on the blocking pool.
+pub struct Blocking<S> {
+    sensor: Arc<Mutex<S>>,
+    name: &'static str,
+    quantities: &'static [Quantity],
+}
+
+impl<S: BlockingSensor> Blocking<S> {
+    pub fn new(sensor: S) -> Self {
+        Self {
+            name: sensor.name(),
+            quantities: sensor.quantities(),
+            sensor: Arc::new(Mutex::new(sensor)),
+        }
+    }
+}
+
+impl<S: BlockingSensor> Sensor for Blocking<S> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        self.quantities
+    }
+
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        let sensor = Arc::clone(&self.sensor);
+        Box::pin(run_blocking(move || {
+            let mut sensor = sensor.lock().unwrap_or_else(PoisonError::into_inner);
+            Ok(sensor.read_blocking()?)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Measurement, Measurements, Quantity, SensorError};
+    use crate::{dht22::Reading, ReadingError};
+
+    use std::{io, time::Duration};
+
+    #[test]
+    fn measurements() {
+        let measurements = Measurements::from(Reading {
+            temperature: 21.5,
+            humidity: 45.0,
+            pressure: None,
+        });
+        assert_eq!(measurements.value(Quantity::Temperature), Some(21.5));
+        assert_eq!(measurements.value(Quantity::Pressure), None);
+        assert_eq!(
+            measurements
+                .iter()
+                .map(Measurement::quantity)
+                .collect::<Vec<_>>(),
+            [Quantity::Temperature, Quantity::Humidity]
+        );
+
+        let light = [Measurement::Illuminance(320.0), Measurement::Light(true)]
+            .into_iter()
+            .collect::<Measurements>();
+        assert_eq!(light.get(Quantity::Light), Some(Measurement::Light(true)));
+        assert_eq!(light.value(Quantity::Light), None);
+        assert_eq!(Quantity::Illuminance.unit(), Some("lx"));
+        assert_eq!("lux".parse(), Ok(Quantity::Illuminance));
+        assert_eq!(
+            Measurement::Humidity(45.0).with_number(47.5),
+            Measurement::Humidity(47.5)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = SensorError::from(ReadingError::Io(io::Error::other("gone")));
+        assert_eq!(error.kind(), "io");
+        assert!(error.reading().is_some());
+
+        let error = SensorError::TooSoon(Duration::from_millis(1500));
+        assert_eq!(error.kind(), "too_soon");
+        assert_eq!(error.to_string(), "read too soon, wait 1500 ms more");
+    }
+}
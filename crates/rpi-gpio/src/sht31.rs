@@ -0,0 +1,145 @@
+//! Sensirion SHT31 temperature and humidity sensor on I2C.
+use rppal::i2c::I2c;
+
+use std::{io, thread::sleep, time::Duration};
+
+use crate::{
+    bus::I2cBus,
+    dht22::Reading,
+    sensor::{BlockingSensor, Measurements, Quantity},
+    ReadingError,
+};
+
+/// Single shot measurement with a high repeatability, without clock stretching.
+const MEASURE_HIGH_REPEATABILITY: [u8; 2] = [0x24, 0x00];
+/// Longest measurement with a high repeatability.
+const MEASUREMENT_TIME: Duration = Duration::from_millis(16);
+
+/// CRC-8 of the words sent by the sensor: polynomial 0x31, initialized to 0xff.
+#[must_use]
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xff, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x31
+            }
+        })
+    })
+}
+
+/// The word at the start of `bytes`, if its CRC is right.
+fn word(bytes: &[u8]) -> Result<u16, ReadingError> {
+    let expected = crc8(&bytes[..2]);
+    if bytes[2] != expected {
+        return Err(ReadingError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "SHT31 CRC mismatch: expected {expected:#04x}, got {:#04x}",
+                bytes[2]
+            ),
+        )));
+    }
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// An SHT31.
+#[derive(Debug)]
+pub struct Sht31<B> {
+    bus: B,
+    address: u8,
+}
+
+impl<B: I2cBus> Sht31<B> {
+    /// Address with ADDR tied to the ground, 0x45 when it's tied to VDD.
+    pub const DEFAULT_ADDRESS: u8 = 0x44;
+
+    pub const fn new(bus: B, address: u8) -> Self {
+        Self { bus, address }
+    }
+
+    /// Run a measurement and read it, blocking for 16 ms.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Io` if the sensor doesn't answer or a CRC is wrong.
+    pub fn read(&mut self) -> Result<Reading, ReadingError> {
+        self.start()?;
+        sleep(MEASUREMENT_TIME);
+        self.fetch()
+    }
+
+    /// Start a measurement, done after 16 ms.
+    fn start(&mut self) -> Result<(), ReadingError> {
+        self.bus.write(self.address, &MEASURE_HIGH_REPEATABILITY)
+    }
+
+    /// Read the result of the measurement.
+    fn fetch(&mut self) -> Result<Reading, ReadingError> {
+        // Temperature and humidity words, each followed by its CRC.
+        let mut data = [0; 6];
+        self.bus.read(self.address, &mut data)?;
+        let temperature = f32::from(word(&data[..3])?);
+        let humidity = f32::from(word(&data[3..])?);
+        Ok(Reading {
+            temperature: 175.0f32.mul_add(temperature / 65535.0, -45.0),
+            humidity: 100.0 * humidity / 65535.0,
+            pressure: None,
+        })
+    }
+}
+
+impl<B: I2cBus + Send + 'static> BlockingSensor for Sht31<B> {
+    fn name(&self) -> &'static str {
+        "SHT31"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Temperature, Quantity::Humidity]
+    }
+
+    fn read_blocking(&mut self) -> Result<Measurements, ReadingError> {
+        Ok(self.read()?.into())
+    }
+}
+
+/// Read the SHT31 at `address` on the I2C `bus`, on the blocking pool.
+///
+/// # Errors
+/// See [`Sht31::read`].
+pub async fn read_async(bus: u8, address: u8) -> Result<Reading, ReadingError> {
+    crate::dht22::run_blocking(move || Sht31::new(I2c::with_bus(bus)?, address).read()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc8, Sht31};
+    use crate::{bus::mock::MockBus, ReadingError};
+
+    #[test]
+    fn crc() {
+        // Example of the datasheet.
+        assert_eq!(crc8(&[0xbe, 0xef]), 0x92);
+    }
+
+    #[test]
+    fn read() {
+        let temperature = [0x66, 0x66];
+        let humidity = [0x80, 0x00];
+        let mut data = temperature.to_vec();
+        data.push(crc8(&temperature));
+        data.extend(humidity);
+        data.push(crc8(&humidity));
+
+        let mut sensor = Sht31::new(MockBus::new(&[&data]), 0x44);
+        let reading = sensor.read().unwrap();
+        assert!((reading.temperature - 25.0).abs() < 0.01);
+        assert!((reading.humidity - 50.0).abs() < 0.01);
+        assert_eq!(sensor.bus.writes, [[0x24, 0x00]]);
+        assert_eq!(sensor.bus.addresses, [0x44; 2]);
+
+        data[5] ^= 0x01;
+        let mut sensor = Sht31::new(MockBus::new(&[&data]), 0x44);
+        assert!(matches!(sensor.read(), Err(ReadingError::Io(_))));
+    }
+}
@@ -0,0 +1,427 @@
+//! Simulated sensors, to run the services and demo the MQTT pipeline without a Raspberry Pi.
+//!
+//! The simulated DHT22 follows the temperature and humidity of a day, and encodes its readings
+//! into pulses, so that they go through the same decoding, recording and error handling as the
+//! real captures. The simulated digital input follows a script of states.
+use std::{
+    f32::consts::TAU,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    dht22::{decode, Dht22, Pulses, Reading, MAX_COUNT},
+    sensor::{BoxFuture, Measurements, Quantity, Sensor, SensorError},
+    ReadingError, TimeoutPhase,
+};
+
+const SECONDS_PER_DAY: f32 = 86_400.0;
+/// Time of the warmest and driest point of the day, in hours after midnight UTC.
+const WARMEST_HOUR: f32 = 15.0;
+
+/// Largest random deviation of a pulse length.
+const JITTER: usize = 4;
+
+/// A xorshift64* generator: good enough for noise, and without dependency.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    const fn new(seed: u64) -> Self {
+        // The state must not be zero.
+        Self(seed | 1)
+    }
+
+    fn from_clock() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        #[allow(clippy::cast_possible_truncation)]
+        Self::new(nanos as u64)
+    }
+
+    const fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number from 0 to 1.
+    #[allow(clippy::cast_precision_loss)]
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A number from `-amplitude` to `amplitude`.
+    fn symmetric(&mut self, amplitude: f32) -> f32 {
+        amplitude * self.unit().mul_add(2.0, -1.0)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    const fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+/// Settings of the simulated DHT22.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationOptions {
+    /// Average temperature of the day, in °C.
+    pub temperature: f32,
+
+    /// Difference between the warmest temperature and the average one.
+    pub temperature_swing: f32,
+
+    /// Average relative humidity of the day, in %, lowest when it's the warmest.
+    pub humidity: f32,
+
+    pub humidity_swing: f32,
+
+    /// Largest random deviation added to each value.
+    pub noise: f32,
+
+    /// Fraction of the readings failing with a checksum mismatch, from 0 to 1.
+    pub checksum_failure_rate: f32,
+
+    /// Fraction of the readings where the sensor stops answering, from 0 to 1.
+    pub timeout_failure_rate: f32,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self {
+            temperature: 21.0,
+            temperature_swing: 3.0,
+            humidity: 50.0,
+            humidity_swing: 10.0,
+            noise: 0.2,
+            checksum_failure_rate: 0.0,
+            timeout_failure_rate: 0.0,
+        }
+    }
+}
+
+impl SimulationOptions {
+    /// The reading without noise at `seconds` after midnight UTC.
+    #[must_use]
+    pub fn diurnal(&self, seconds: f32) -> Reading {
+        let hours = seconds / 3600.0;
+        let phase = (TAU * (hours - WARMEST_HOUR) / 24.0).cos();
+        Reading {
+            temperature: self.temperature_swing.mul_add(phase, self.temperature),
+            humidity: (-self.humidity_swing).mul_add(phase, self.humidity),
+            pressure: None,
+        }
+    }
+}
+
+/// The five bytes a DHT22 sends for `reading`, see [`frame`].
+fn encode(reading: Reading) -> [u8; 5] {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let humidity = (reading.humidity.clamp(0.0, 100.0) * 10.0).round() as u16;
+    #[allow(clippy::cast_possible_truncation)]
+    let temperature = (reading.temperature.clamp(-125.0, 125.0) * 10.0).round() as i16;
+    frame(humidity, temperature)
+}
+
+/// The five bytes a DHT22 sends for a humidity and a temperature in tenths: both big endian,
+/// the sign of the temperature in the highest bit, then the checksum.
+#[must_use]
+pub const fn frame(humidity: u16, temperature: i16) -> [u8; 5] {
+    let sign = if temperature < 0 { 0x8000 } else { 0 };
+    let [h1, h2] = humidity.to_be_bytes();
+    let [t1, t2] = (temperature.unsigned_abs() | sign).to_be_bytes();
+    let checksum = h1.wrapping_add(h2).wrapping_add(t1).wrapping_add(t2);
+    [h1, h2, t1, t2, checksum]
+}
+
+/// Lengths of the pulses of a DHT22 answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PulseLengths {
+    /// Each of the two pulses answering the start signal.
+    pub answer: usize,
+    /// The low period before each bit.
+    pub low: usize,
+    /// The high period of a 0.
+    pub zero: usize,
+    /// The high period of a 1.
+    pub one: usize,
+}
+
+impl PulseLengths {
+    /// The microseconds of the datasheet, about as many busy-loop iterations on a
+    /// Raspberry Pi 3.
+    pub const NOMINAL: Self = Self {
+        answer: 80,
+        low: 50,
+        zero: 26,
+        one: 70,
+    };
+}
+
+/// The capture of `data` sent with `lengths`, each pulse passed in order through `adjust`, to
+/// add some jitter for example.
+pub fn pulses(
+    data: [u8; 5],
+    lengths: PulseLengths,
+    mut adjust: impl FnMut(usize) -> usize,
+) -> Pulses {
+    let mut pulses: Pulses = [0; 82];
+    pulses[0] = adjust(lengths.answer);
+    pulses[1] = adjust(lengths.answer);
+    for bit in 0..40 {
+        pulses[2 + bit * 2] = adjust(lengths.low);
+        let one = data[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        pulses[3 + bit * 2] = adjust(if one { lengths.one } else { lengths.zero });
+    }
+    pulses
+}
+
+/// A DHT22 following the temperature and humidity of a day.
+#[derive(Debug, Clone)]
+pub struct SimulatedDht22 {
+    options: SimulationOptions,
+    rng: Rng,
+}
+
+impl SimulatedDht22 {
+    /// A sensor seeded from the clock.
+    #[must_use]
+    pub fn new(options: SimulationOptions) -> Self {
+        Self {
+            options,
+            rng: Rng::from_clock(),
+        }
+    }
+
+    /// A sensor with reproducible noise and failures.
+    #[must_use]
+    pub const fn with_seed(options: SimulationOptions, seed: u64) -> Self {
+        Self {
+            options,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// The pulses of a reading now.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Timeout` at the configured rate.
+    pub fn capture(&mut self) -> Result<Pulses, ReadingError> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() % 86_400);
+        #[allow(clippy::cast_precision_loss)]
+        self.capture_at(seconds as f32)
+    }
+
+    /// The pulses of a reading at `seconds` after midnight UTC.
+    ///
+    /// # Errors
+    /// Returns `ReadingError::Timeout` at the configured rate.
+    pub fn capture_at(&mut self, seconds: f32) -> Result<Pulses, ReadingError> {
+        let mut reading = self.options.diurnal(seconds % SECONDS_PER_DAY);
+        reading.temperature += self.rng.symmetric(self.options.noise);
+        reading.humidity += self.rng.symmetric(self.options.noise);
+        let mut data = encode(reading);
+
+        let failure = self.rng.unit();
+        let checksum_failure = failure < self.options.checksum_failure_rate;
+        let timeout = !checksum_failure
+            && failure < self.options.checksum_failure_rate + self.options.timeout_failure_rate;
+        if checksum_failure {
+            data[4] ^= 0x80;
+        }
+
+        let lost = timeout.then(|| self.rng.below(40));
+        let pulses = pulses(data, PulseLengths::NOMINAL, |length| self.jitter(length));
+        if let Some(bit) = lost {
+            // The low period was captured, the high one never ended: like a real capture, the
+            // last pulse is the count that overflowed.
+            let mut captured = pulses[..4 + bit * 2].to_vec();
+            captured[3 + bit * 2] = MAX_COUNT + 1;
+            return Err(ReadingError::Timeout {
+                phase: TimeoutPhase::BitHigh(bit),
+                pulses: captured,
+            });
+        }
+        Ok(pulses)
+    }
+
+    const fn jitter(&mut self, length: usize) -> usize {
+        length - JITTER + self.rng.below(2 * JITTER + 1)
+    }
+}
+
+impl Sensor for SimulatedDht22 {
+    fn name(&self) -> &'static str {
+        "simulated DHT22"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Temperature, Quantity::Humidity]
+    }
+
+    fn min_interval(&self) -> Duration {
+        Dht22::MIN_INTERVAL
+    }
+
+    fn read(&mut self) -> BoxFuture<'_, Result<Measurements, SensorError>> {
+        Box::pin(async move { Ok(decode(&self.capture()?)?.into()) })
+    }
+}
+
+/// States of a simulated digital input, each held for a while, repeated forever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script(Vec<(bool, Duration)>);
+
+impl Script {
+    /// The states and how long they're held.
+    #[must_use]
+    pub fn steps(&self) -> &[(bool, Duration)] {
+        &self.0
+    }
+}
+
+impl Default for Script {
+    /// Light for a minute, then dark for a minute.
+    fn default() -> Self {
+        Self(vec![
+            (true, Duration::from_secs(60)),
+            (false, Duration::from_secs(60)),
+        ])
+    }
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    /// Parse a comma-separated list of states and durations in seconds, such as
+    /// `on:30,off:90`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| {
+                let (state, seconds) = step
+                    .split_once(':')
+                    .ok_or_else(|| format!("step {step} isn't state:seconds"))?;
+                let state = match state {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("unknown state {state}, expected on or off")),
+                };
+                let seconds = seconds
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|seconds| *seconds > 0.0)
+                    .ok_or_else(|| format!("invalid duration {seconds}"))?;
+                Ok((state, Duration::from_secs_f32(seconds)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if steps.is_empty() {
+            return Err("the script is empty".to_string());
+        }
+        Ok(Self(steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, Script, SimulatedDht22, SimulationOptions};
+    use crate::{
+        dht22::{analyze, decode, Reading, MAX_COUNT},
+        ReadingError, TimeoutPhase,
+    };
+
+    use std::time::Duration;
+
+    #[test]
+    fn diurnal() {
+        let options = SimulationOptions::default();
+        let afternoon = options.diurnal(15.0 * 3600.0);
+        assert!((afternoon.temperature - 24.0).abs() < 1e-3);
+        assert!((afternoon.humidity - 40.0).abs() < 1e-3);
+        let night = options.diurnal(3.0 * 3600.0);
+        assert!((night.temperature - 18.0).abs() < 1e-3);
+        assert!((night.humidity - 60.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pulses_decode() {
+        let reading = Reading {
+            temperature: -3.5,
+            humidity: 65.2,
+            pressure: None,
+        };
+        assert_eq!(encode(reading), [0x02, 0x8c, 0x80, 0x23, 0x31]);
+
+        let options = SimulationOptions {
+            noise: 0.0,
+            ..SimulationOptions::default()
+        };
+        let mut sensor = SimulatedDht22::with_seed(options, 7);
+        for hour in 0..24 {
+            #[allow(clippy::cast_precision_loss)]
+            let seconds = hour as f32 * 3600.0;
+            let expected = options.diurnal(seconds);
+            let reading = decode(&sensor.capture_at(seconds).unwrap()).unwrap();
+            assert!((reading.temperature - expected.temperature).abs() <= 0.05);
+            assert!((reading.humidity - expected.humidity).abs() <= 0.05);
+        }
+    }
+
+    #[test]
+    fn failures() {
+        let checksum = SimulationOptions {
+            checksum_failure_rate: 1.0,
+            ..SimulationOptions::default()
+        };
+        let mut sensor = SimulatedDht22::with_seed(checksum, 7);
+        let pulses = sensor.capture_at(0.0).unwrap();
+        assert!(matches!(
+            decode(&pulses),
+            Err(ReadingError::Checksum { .. })
+        ));
+
+        let timeout = SimulationOptions {
+            timeout_failure_rate: 1.0,
+            ..SimulationOptions::default()
+        };
+        let mut sensor = SimulatedDht22::with_seed(timeout, 7);
+        let Err(ReadingError::Timeout { phase, pulses }) = sensor.capture_at(0.0) else {
+            panic!("expected a timeout");
+        };
+        let TimeoutPhase::BitHigh(bit) = phase else {
+            panic!("expected a timeout on a high period, got {phase}");
+        };
+        // Up to the high period of the lost bit, which overflowed, without a pulse left out.
+        assert_eq!(pulses.len(), 4 + bit * 2);
+        assert!(pulses.iter().all(|&length| length > 0));
+        assert_eq!(pulses.last(), Some(&(MAX_COUNT + 1)));
+
+        // Replaying the capture finds the same phase.
+        let replayed = analyze(&pulses).result;
+        assert!(
+            matches!(&replayed, Err(ReadingError::Timeout { phase: replayed, .. }) if *replayed == phase),
+            "{replayed:?}"
+        );
+    }
+
+    #[test]
+    fn script() {
+        let script = "on:30, off:1.5".parse::<Script>().unwrap();
+        assert_eq!(
+            script.steps(),
+            [
+                (true, Duration::from_secs(30)),
+                (false, Duration::from_millis(1500))
+            ]
+        );
+        assert!("on:30,dim:10".parse::<Script>().is_err());
+        assert!("on:0".parse::<Script>().is_err());
+        assert!("".parse::<Script>().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! State of a running service, as reported by the status endpoint.
+use serde_json::{json, Value};
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Placeholder for secrets in configuration summaries.
+pub const REDACTED: &str = "<redacted>";
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+/// What a service is currently doing.
+#[derive(Debug)]
+pub struct Status {
+    started_at: Instant,
+    last_reading: Option<Value>,
+    last_reading_at: Option<SystemTime>,
+    consecutive_failures: u32,
+    mqtt_connected: bool,
+    queue_depth: usize,
+    config: Value,
+}
+
+impl Status {
+    /// `config` is a summary of the configuration, secrets must already be redacted.
+    #[must_use]
+    pub fn new(config: Value) -> Self {
+        Self {
+            started_at: Instant::now(),
+            last_reading: None,
+            last_reading_at: None,
+            consecutive_failures: 0,
+            mqtt_connected: false,
+            queue_depth: 0,
+            config,
+        }
+    }
+
+    #[must_use]
+    pub fn shared(config: Value) -> SharedStatus {
+        Arc::new(Mutex::new(Self::new(config)))
+    }
+
+    /// Record a successful reading.
+    pub fn reading(&mut self, reading: Value) {
+        self.last_reading = Some(reading);
+        self.last_reading_at = Some(SystemTime::now());
+        self.consecutive_failures = 0;
+    }
+
+    /// Record a failed reading.
+    pub const fn failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    }
+
+    /// Record the state of the connection to the broker, `None` if it didn't change, and the
+    /// number of requests waiting to be sent or acknowledged.
+    pub const fn connection(&mut self, connected: Option<bool>, queue_depth: usize) {
+        if let Some(connected) = connected {
+            self.mqtt_connected = connected;
+        }
+        self.queue_depth = queue_depth;
+    }
+
+    pub fn set_config(&mut self, config: Value) {
+        self.config = config;
+    }
+
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "last_reading": self.last_reading,
+            "last_reading_at": self.last_reading_at.and_then(|at| {
+                at.duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs())
+            }),
+            "consecutive_failures": self.consecutive_failures,
+            "mqtt_connected": self.mqtt_connected,
+            "queue_depth": self.queue_depth,
+            "uptime": self.started_at.elapsed().as_secs(),
+            "config": self.config,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Status;
+
+    use serde_json::json;
+
+    #[test]
+    fn failures_reset_on_reading() {
+        let mut status = Status::new(json!({}));
+        status.failure();
+        status.failure();
+        assert_eq!(status.to_json()["consecutive_failures"], 2);
+        assert!(status.to_json()["last_reading_at"].is_null());
+
+        status.reading(json!({ "temperature": "21.5" }));
+        let json = status.to_json();
+        assert_eq!(json["consecutive_failures"], 0);
+        assert_eq!(json["last_reading"]["temperature"], "21.5");
+        assert!(json["last_reading_at"].is_u64());
+    }
+}
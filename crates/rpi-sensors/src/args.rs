@@ -0,0 +1,273 @@
+use rpi_gpio::{dht22::DecodeOptions, light::InputOptions, simulated::Script, Backend};
+
+use std::str::FromStr;
+
+pub const USAGE: &str = "Usage: rpi-sensors [--json] <command>
+
+Commands:
+  read dht22 --pin <pin> [--backend <backend>] [--line <line>] [--recovery <heuristics>]
+             [--calibration <file>]
+  read light --pin <pin> [--backend <backend>] [--line <line>] [--pull <pull>] [--inverted]
+             [--script <script>]
+  publish-once    read the sensor of the temperature service once and publish the reading
+  check-config    validate the configuration of the temperature service
+  test-broker     publish a message to the broker and wait for it to come back
+
+The last three read the environment and .env like the temperature service.
+
+Exit codes: 0 success, 1 failure, 2 usage, 3 timeout, 4 checksum, 5 gpio, 6 io, 7 realtime";
+
+/// Where and how a sensor is wired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wiring {
+    pub pin: u8,
+    pub backend: Backend,
+    /// Line of the cdev backend, the offset of the pin on gpiochip0 if unset.
+    pub line: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    ReadDht22 {
+        wiring: Wiring,
+        recovery: DecodeOptions,
+        /// JSON file of the calibrations, the one of the pin applies.
+        calibration: Option<String>,
+    },
+    ReadLight {
+        wiring: Wiring,
+        input: InputOptions,
+        /// States of the simulated input, whose first one is read.
+        script: Script,
+    },
+    PublishOnce,
+    CheckConfig,
+    TestBroker,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    /// Print JSON instead of text.
+    pub json: bool,
+    pub command: Command,
+}
+
+fn parse<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String>
+where
+    T::Err: ToString,
+{
+    let value = value.ok_or_else(|| format!("{flag} needs a value"))?;
+    value
+        .parse()
+        .map_err(|e: T::Err| format!("{flag} is invalid: {}", e.to_string()))
+}
+
+/// Options of `read`, common to both sensors.
+fn read_options(sensor: &str, options: &[String]) -> Result<Command, String> {
+    if sensor != "dht22" && sensor != "light" {
+        return Err(format!("unknown sensor {sensor}, expected dht22 or light"));
+    }
+    let mut pin = None;
+    let mut backend = Backend::default();
+    let mut line = None;
+    let mut recovery = DecodeOptions::default();
+    let mut calibration = None;
+    let mut input = InputOptions::default();
+    // The last of the options wiring the input, which the simulated backend has none of.
+    let mut gpio_option = None;
+    let mut script = None;
+
+    let mut options = options.iter();
+    while let Some(flag) = options.next() {
+        match flag.as_str() {
+            "--pin" => pin = Some(parse::<u8>(flag, options.next())?),
+            "--backend" => backend = parse(flag, options.next())?,
+            "--line" => line = Some(parse(flag, options.next())?),
+            "--recovery" if sensor == "dht22" => recovery = parse(flag, options.next())?,
+            "--calibration" if sensor == "dht22" => {
+                calibration = Some(parse(flag, options.next())?);
+            }
+            "--pull" if sensor == "light" => {
+                input.pull = parse(flag, options.next())?;
+                gpio_option = Some(flag);
+            }
+            "--inverted" if sensor == "light" => {
+                input.inverted = true;
+                gpio_option = Some(flag);
+            }
+            "--script" if sensor == "light" => script = Some(parse(flag, options.next())?),
+            _ => return Err(format!("unknown option {flag} for {sensor}")),
+        }
+    }
+
+    // Without the cdev feature, there's no such backend to compare with.
+    if line.is_some() && backend.name() != "cdev" {
+        return Err("--line needs the cdev backend".to_string());
+    }
+    if script.is_some() && backend != Backend::Simulated {
+        return Err("--script needs the simulated backend".to_string());
+    }
+    if let Some(flag) = gpio_option.filter(|_| backend == Backend::Simulated) {
+        return Err(format!("{flag} needs a GPIO backend"));
+    }
+    let wiring = Wiring {
+        pin: pin.ok_or("--pin is missing")?,
+        backend,
+        line,
+    };
+    if sensor == "dht22" {
+        Ok(Command::ReadDht22 {
+            wiring,
+            recovery,
+            calibration,
+        })
+    } else {
+        Ok(Command::ReadLight {
+            wiring,
+            input,
+            script: script.unwrap_or_default(),
+        })
+    }
+}
+
+impl Args {
+    /// Parse the arguments, without the name of the binary.
+    ///
+    /// # Errors
+    /// Returns a message if the arguments don't match the usage.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let (json, args) = match args {
+            [flag, rest @ ..] if flag == "--json" => (true, rest),
+            _ => (false, args),
+        };
+        let command = match args {
+            [command, sensor, options @ ..] if command == "read" => read_options(sensor, options)?,
+            [command] if command == "publish-once" => Command::PublishOnce,
+            [command] if command == "check-config" => Command::CheckConfig,
+            [command] if command == "test-broker" => Command::TestBroker,
+            [command] if command == "read" => {
+                return Err("read needs a sensor, dht22 or light".to_string())
+            }
+            [command, argument, ..]
+                if ["publish-once", "check-config", "test-broker"].contains(&command.as_str()) =>
+            {
+                return Err(format!("unexpected argument {argument} for {command}"))
+            }
+            [command, ..] => return Err(format!("unknown command {command}")),
+            [] => return Err("missing command".to_string()),
+        };
+        Ok(Self { json, command })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Args, Command, Wiring};
+    use rpi_gpio::{dht22::DecodeOptions, light::InputOptions, simulated::Script, Backend, Pull};
+
+    fn parse(args: &str) -> Result<Args, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Args::parse(&args)
+    }
+
+    #[test]
+    fn read() {
+        assert_eq!(
+            parse("read dht22 --pin 4"),
+            Ok(Args {
+                json: false,
+                command: Command::ReadDht22 {
+                    wiring: Wiring {
+                        pin: 4,
+                        backend: Backend::Rppal,
+                        line: None,
+                    },
+                    recovery: DecodeOptions::default(),
+                    calibration: None,
+                },
+            })
+        );
+        assert_eq!(
+            parse("--json read light --pin 27 --pull up --inverted"),
+            Ok(Args {
+                json: true,
+                command: Command::ReadLight {
+                    wiring: Wiring {
+                        pin: 27,
+                        backend: Backend::Rppal,
+                        line: None,
+                    },
+                    input: InputOptions {
+                        pull: Pull::Up,
+                        inverted: true,
+                    },
+                    script: Script::default(),
+                },
+            })
+        );
+        assert_eq!(
+            parse("read light --pin 27 --backend simulated --script off:5")
+                .map(|args| args.command),
+            Ok(Command::ReadLight {
+                wiring: Wiring {
+                    pin: 27,
+                    backend: Backend::Simulated,
+                    line: None,
+                },
+                input: InputOptions::default(),
+                script: "off:5".parse().unwrap(),
+            })
+        );
+        assert_eq!(
+            parse("read dht22 --pin 4 --recovery all --calibration calibration.json")
+                .map(|args| args.command),
+            Ok(Command::ReadDht22 {
+                wiring: Wiring {
+                    pin: 4,
+                    backend: Backend::Rppal,
+                    line: None,
+                },
+                recovery: DecodeOptions::ALL,
+                calibration: Some("calibration.json".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse(""), Err("missing command".to_string()));
+        assert_eq!(parse("reset"), Err("unknown command reset".to_string()));
+        assert_eq!(parse("read dht22"), Err("--pin is missing".to_string()));
+        assert_eq!(
+            parse("read dht22 --pin"),
+            Err("--pin needs a value".to_string())
+        );
+        assert_eq!(
+            parse("read dht22 --pin 4 --inverted"),
+            Err("unknown option --inverted for dht22".to_string())
+        );
+        assert_eq!(
+            parse("read bme280 --address 76"),
+            Err("unknown sensor bme280, expected dht22 or light".to_string())
+        );
+        assert_eq!(
+            parse("read dht22 --pin 4 --line GPIO4"),
+            Err("--line needs the cdev backend".to_string())
+        );
+        assert_eq!(
+            parse("read light --pin 27 --script on:5"),
+            Err("--script needs the simulated backend".to_string())
+        );
+        assert_eq!(
+            parse("read light --pin 27 --backend simulated --inverted"),
+            Err("--inverted needs a GPIO backend".to_string())
+        );
+        assert_eq!(
+            parse("publish-once now"),
+            Err("unexpected argument now for publish-once".to_string())
+        );
+        assert!(parse("read dht22 --pin 300")
+            .unwrap_err()
+            .starts_with("--pin is invalid"));
+    }
+}
@@ -0,0 +1,474 @@
+mod args;
+
+use args::{Args, Command, Wiring, USAGE};
+use rpi_gpio::{
+    calibration::Calibrations,
+    dht22::{capture_async_with, decode_with, DecodeOptions},
+    light::{self, InputOptions},
+    metrics::Metrics,
+    realtime,
+    sensor::{Measurement, Measurements, SensorError},
+    simulated::{Script, SimulatedDht22, SimulationOptions},
+    status::Status,
+    tls::load_certs,
+    Backend, ReadingError,
+};
+#[cfg(feature = "cdev")]
+use rpi_gpio::{cdev::LineSpec, dht22::capture_line_async};
+use rumqttc::{
+    v5::{
+        mqttbytes::{v5::Packet, QoS},
+        AsyncClient, Event, EventLoop, MqttOptions,
+    },
+    Outgoing, Transport,
+};
+use serde_json::{json, Map, Value};
+use temperature::{
+    config::Config,
+    reading::{publish_properties, read_sensor, topic, Message, OpenedSensor, Reporting},
+};
+use tokio::time::{timeout, Instant};
+
+use std::{env, fmt::Display, process::ExitCode, sync::Arc, time::Duration};
+
+/// Exit codes besides the ones of the reading errors.
+const FAILURE: u8 = 1;
+const USAGE_ERROR: u8 = 2;
+
+/// How long to wait for each answer of the broker.
+const BROKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Exit code of a failed reading, so that scripts can tell a wiring problem from a noisy line.
+const fn exit_code(error: &ReadingError) -> u8 {
+    match error {
+        ReadingError::Timeout { .. } => 3,
+        ReadingError::Checksum { .. } => 4,
+        ReadingError::Gpio(_) => 5,
+        ReadingError::Io(_) => 6,
+        ReadingError::Realtime(_) => 7,
+    }
+}
+
+/// Why a command failed.
+#[derive(Debug)]
+enum Failure {
+    Sensor(SensorError),
+    Other(String),
+}
+
+impl Failure {
+    fn other(e: impl Display) -> Self {
+        Self::Other(e.to_string())
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            Self::Sensor(e) => e.reading().map_or(FAILURE, exit_code),
+            Self::Other(_) => FAILURE,
+        }
+    }
+
+    const fn kind(&self) -> &'static str {
+        match self {
+            Self::Sensor(e) => e.kind(),
+            Self::Other(_) => "error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Sensor(e) => e.to_string(),
+            Self::Other(message) => message.clone(),
+        }
+    }
+}
+
+impl From<SensorError> for Failure {
+    fn from(err: SensorError) -> Self {
+        Self::Sensor(err)
+    }
+}
+
+impl From<ReadingError> for Failure {
+    fn from(err: ReadingError) -> Self {
+        Self::Sensor(err.into())
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+/// Result of a command, printed as text or JSON.
+struct Output {
+    text: String,
+    json: Value,
+}
+
+/// A value rounded to a decimal, as precise as the sensors.
+fn tenths(value: f32) -> f64 {
+    (f64::from(value) * 10.0).round() / 10.0
+}
+
+impl From<&Measurements> for Output {
+    fn from(measurements: &Measurements) -> Self {
+        let mut text = Vec::new();
+        let mut json = Map::new();
+        for measurement in measurements.iter() {
+            let key = measurement.quantity().key();
+            let (line, value) = match (measurement, measurement.quantity().unit()) {
+                (Measurement::Light(light), _) => (format!("{key}: {light}"), json!(light)),
+                (_, unit) => {
+                    let value = tenths(measurement.number().unwrap_or_default());
+                    let unit = unit.map_or_else(String::new, |unit| format!(" {unit}"));
+                    (format!("{key}: {value:.1}{unit}"), json!(value))
+                }
+            };
+            text.push(line);
+            json.insert(key.to_string(), value);
+        }
+        Self {
+            text: text.join("\n"),
+            json: Value::Object(json),
+        }
+    }
+}
+
+#[cfg(feature = "cdev")]
+fn line_spec(wiring: &Wiring) -> Result<LineSpec, Failure> {
+    wiring.line.as_deref().map_or_else(
+        || Ok(LineSpec::offset(u32::from(wiring.pin))),
+        |line| {
+            line.parse()
+                .map_err(|e| Failure::Other(format!("--line is invalid: {e}")))
+        },
+    )
+}
+
+async fn read_dht22(
+    wiring: &Wiring,
+    recovery: DecodeOptions,
+    calibration: Option<&str>,
+) -> Result<Output, Failure> {
+    let calibrations =
+        calibration.map_or_else(|| Ok(Calibrations::default()), Calibrations::load)?;
+    let captured = match wiring.backend {
+        Backend::Rppal => capture_async_with(wiring.pin, None).await,
+        #[cfg(feature = "cdev")]
+        Backend::Cdev => capture_line_async(line_spec(wiring)?, None).await,
+        Backend::Simulated => SimulatedDht22::new(SimulationOptions::default()).capture(),
+    };
+    let decoded = captured.and_then(|pulses| decode_with(&pulses, recovery))?;
+    let calibrated =
+        calibrations.calibrate(&wiring.pin.to_string(), Measurements::from(decoded.reading));
+    let mut output = Output::from(&calibrated.measurements);
+    if let Some(raw) = &calibrated.raw {
+        let raw = Output::from(raw);
+        for line in raw.text.lines() {
+            output.text = format!("{}\nraw {line}", output.text);
+        }
+        output.json["raw"] = raw.json;
+    }
+    if let Some(recovery) = decoded.recovery {
+        output.text = format!("{}\nrecovered with {recovery}", output.text);
+        output.json["recovery"] = json!(recovery.to_string());
+    }
+    Ok(output)
+}
+
+fn read_light(wiring: &Wiring, input: InputOptions, script: &Script) -> Result<Output, Failure> {
+    let light = match wiring.backend {
+        Backend::Rppal => light::read(wiring.pin, input)?,
+        #[cfg(feature = "cdev")]
+        Backend::Cdev => light::read_line(&line_spec(wiring)?, input)?,
+        // The simulated input starts in the state of the first step.
+        Backend::Simulated => script.steps()[0].0,
+    };
+    Ok(Output::from(&Measurements::from(Measurement::Light(light))))
+}
+
+/// A client of the broker of `config`, with its own id so that it doesn't disconnect the
+/// service.
+fn connect(config: &Config, purpose: &str) -> Result<(AsyncClient, EventLoop), Failure> {
+    let mut options = MqttOptions::new(
+        format!("{}-{purpose}", config.client_id),
+        &config.mqtt_ip,
+        config.mqtt_port,
+    );
+    options
+        .set_keep_alive(Duration::from_secs(5))
+        .set_clean_start(true)
+        .set_credentials(&config.mqtt_username, &config.mqtt_password);
+    let tls = load_certs(
+        config.ca_cert_path.clone(),
+        config.mtls_pkey_path.clone(),
+        config.mtls_cert_path.clone(),
+    )
+    .map_err(|e| Failure::Other(format!("Can't load the certificates: {e}")))?;
+    if let Some(tls) = tls {
+        options.set_transport(Transport::tls_with_config(tls));
+    }
+    Ok(AsyncClient::new(options, 50))
+}
+
+/// Drive the connection until `done` accepts an event.
+async fn wait_for(
+    eventloop: &mut EventLoop,
+    what: &str,
+    mut done: impl FnMut(&Event) -> bool,
+) -> Result<(), Failure> {
+    let waited = timeout(BROKER_TIMEOUT, async {
+        loop {
+            let event = eventloop.poll().await.map_err(Failure::other)?;
+            if done(&event) {
+                return Ok(());
+            }
+        }
+    })
+    .await;
+    waited.map_err(|_| Failure::Other(format!("the broker didn't answer in time: {what}")))?
+}
+
+async fn disconnect(client: &AsyncClient, eventloop: &mut EventLoop) {
+    if client.disconnect().await.is_ok() {
+        let _ = wait_for(eventloop, "disconnect", |event| {
+            matches!(event, Event::Outgoing(Outgoing::Disconnect))
+        })
+        .await;
+    }
+}
+
+/// Read the configured sensor once and publish the reading like the service.
+async fn publish_once() -> Result<Output, Failure> {
+    let config = Config::load(false)?;
+    let reporting = Reporting {
+        status: Status::shared(config.summary()),
+        metrics: Arc::new(Metrics::new()),
+        recorder: None,
+    };
+    let realtime = config.realtime_for(1);
+    if realtime.is_some() {
+        realtime::lock_memory()?;
+    }
+    let measured = read_sensor(&config, realtime, &reporting, &mut OpenedSensor::default()).await?;
+    let properties = publish_properties(&config, 1, config.delay, measured.recovery());
+    let messages: Vec<(String, Message)> = measured
+        .messages()
+        .into_iter()
+        .map(|message| (topic(&config, message.id.as_deref()), message))
+        .collect();
+
+    let (client, mut eventloop) = connect(&config, "publish")?;
+    for (topic, message) in &messages {
+        client
+            .publish_with_properties(
+                topic,
+                QoS::AtLeastOnce,
+                false,
+                message.payload.to_string(),
+                message.properties(&properties),
+            )
+            .await
+            .map_err(Failure::other)?;
+    }
+    let mut acknowledged = 0;
+    wait_for(&mut eventloop, "publish", |event| {
+        if matches!(event, Event::Incoming(Packet::PubAck(_))) {
+            acknowledged += 1;
+        }
+        acknowledged == messages.len()
+    })
+    .await?;
+    disconnect(&client, &mut eventloop).await;
+
+    Ok(Output {
+        text: messages
+            .iter()
+            .map(|(topic, message)| {
+                let raw = message
+                    .raw
+                    .as_ref()
+                    .map_or_else(String::new, |raw| format!(" (raw {raw})"));
+                format!("{topic}: {}{raw}", message.payload)
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        json: messages
+            .into_iter()
+            .map(|(topic, message)| {
+                json!({ "topic": topic, "payload": message.payload, "raw": message.raw })
+            })
+            .collect(),
+    })
+}
+
+fn check_config() -> Result<Output, Failure> {
+    let config = Config::load(false)?;
+    load_certs(
+        config.ca_cert_path.clone(),
+        config.mtls_pkey_path.clone(),
+        config.mtls_cert_path.clone(),
+    )
+    .map_err(|e| Failure::Other(format!("Can't load the certificates: {e}")))?;
+
+    let summary = config.summary();
+    let text = summary.as_object().map_or_else(String::new, |summary| {
+        summary
+            .iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => format!("{key}: {value}"),
+                _ => format!("{key}: {value}"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    Ok(Output {
+        text,
+        json: summary,
+    })
+}
+
+/// Publish a message on a subtopic of the service and wait for the broker to send it back.
+async fn test_broker() -> Result<Output, Failure> {
+    let config = Config::load(false)?;
+    let broker = format!("{}:{}", config.mqtt_ip, config.mqtt_port);
+    let topic = format!("{}/test-broker", config.mqtt_topic);
+    let (client, mut eventloop) = connect(&config, "test")?;
+
+    let started = Instant::now();
+    wait_for(&mut eventloop, "connect", |event| {
+        matches!(event, Event::Incoming(Packet::ConnAck(_)))
+    })
+    .await?;
+    let connected = started.elapsed();
+
+    client
+        .subscribe(&topic, QoS::AtLeastOnce)
+        .await
+        .map_err(Failure::other)?;
+    wait_for(&mut eventloop, "subscribe", |event| {
+        matches!(event, Event::Incoming(Packet::SubAck(_)))
+    })
+    .await?;
+
+    let payload = format!("rpi-sensors {}", std::process::id());
+    let sent = Instant::now();
+    client
+        .publish(&topic, QoS::AtLeastOnce, false, payload.clone())
+        .await
+        .map_err(Failure::other)?;
+    wait_for(&mut eventloop, "round trip", |event| {
+        matches!(event, Event::Incoming(Packet::Publish(publish)) if publish.payload == payload)
+    })
+    .await?;
+    let round_trip = sent.elapsed();
+    disconnect(&client, &mut eventloop).await;
+
+    let tls = config.ca_cert_path.is_some();
+    Ok(Output {
+        text: format!(
+            "connected to {broker}{} in {} ms, round trip in {} ms",
+            if tls { " with TLS" } else { "" },
+            connected.as_millis(),
+            round_trip.as_millis()
+        ),
+        json: json!({
+            "broker": broker,
+            "tls": tls,
+            "connect_ms": connected.as_millis(),
+            "round_trip_ms": round_trip.as_millis(),
+        }),
+    })
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match Args::parse(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::from(USAGE_ERROR);
+        }
+    };
+
+    let result = match &args.command {
+        Command::ReadDht22 {
+            wiring,
+            recovery,
+            calibration,
+        } => read_dht22(wiring, *recovery, calibration.as_deref()).await,
+        Command::ReadLight {
+            wiring,
+            input,
+            script,
+        } => read_light(wiring, *input, script),
+        Command::PublishOnce => publish_once().await,
+        Command::CheckConfig => check_config(),
+        Command::TestBroker => test_broker().await,
+    };
+
+    match result {
+        Ok(output) if args.json => println!("{}", output.json),
+        Ok(output) => println!("{}", output.text),
+        Err(failure) => {
+            if args.json {
+                println!(
+                    "{}",
+                    json!({ "error": failure.kind(), "message": failure.message() })
+                );
+            } else {
+                eprintln!("error: {}", failure.message());
+            }
+            return ExitCode::from(failure.code());
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exit_code, Failure, Output};
+    use rpi_gpio::{
+        sensor::{Measurement, Measurements},
+        ReadingError, TimeoutPhase,
+    };
+
+    use serde_json::json;
+    use std::io;
+
+    #[test]
+    fn exit_codes() {
+        let timeout = ReadingError::Timeout {
+            phase: TimeoutPhase::StartHandshake,
+            pulses: Vec::new(),
+        };
+        assert_eq!(exit_code(&timeout), 3);
+        let failure = Failure::from(ReadingError::Io(io::Error::other("no bus")));
+        assert_eq!((failure.code(), failure.kind()), (6, "io"));
+        let failure = Failure::Other("no broker".to_string());
+        assert_eq!((failure.code(), failure.kind()), (1, "error"));
+    }
+
+    #[test]
+    fn output() {
+        let measurements = [
+            Measurement::Temperature(21.44),
+            Measurement::Humidity(48.0),
+            Measurement::Light(true),
+        ]
+        .into_iter()
+        .collect::<Measurements>();
+        let output = Output::from(&measurements);
+        assert_eq!(
+            output.text,
+            "temperature: 21.4 °C\nhumidity: 48.0 %\nlight: true"
+        );
+        assert_eq!(
+            output.json,
+            json!({ "temperature": 21.4, "humidity": 48.0, "light": true })
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! The CLI on the simulated backend, against an embedded broker.
+use std::{process::Output, time::Duration};
+
+use harness::{run, Broker, Subscriber, TempDir};
+use serde_json::Value;
+
+const TOPIC: &str = "test/cli";
+const WAIT: Duration = Duration::from_secs(15);
+
+fn rpi_sensors(args: &[&str], vars: &[(&str, String)]) -> Output {
+    run(env!("CARGO_BIN_EXE_rpi-sensors"), args, vars)
+}
+
+/// The configuration of the temperature service, on the simulated backend.
+fn config(port: u16) -> Vec<(&'static str, String)> {
+    vec![
+        ("MQTT_IP", "127.0.0.1".to_string()),
+        ("MQTT_PORT", port.to_string()),
+        ("MQTT_USERNAME", "user".to_string()),
+        ("MQTT_PASSWORD", "password".to_string()),
+        ("TEMPERATURE_MQTT_CLIENT_ID", "cli".to_string()),
+        ("TEMPERATURE_MQTT_TOPIC", TOPIC.to_string()),
+        ("TEMPERATURE_MQTT_DELAY", "60".to_string()),
+        ("TEMPERATURE_DHT_PIN", "4".to_string()),
+        ("TEMPERATURE_GPIO_BACKEND", "simulated".to_string()),
+    ]
+}
+
+fn json(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn read() {
+    let output = rpi_sensors(
+        &[
+            "--json",
+            "read",
+            "dht22",
+            "--pin",
+            "4",
+            "--backend",
+            "simulated",
+        ],
+        &[],
+    );
+    assert!(output.status.success());
+    let reading = json(&output);
+    assert!(reading["temperature"].is_f64(), "{reading}");
+    assert!(reading["humidity"].is_f64(), "{reading}");
+
+    let output = rpi_sensors(
+        &["read", "light", "--pin", "27", "--backend", "simulated"],
+        &[],
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "light: true\n");
+
+    let output = rpi_sensors(
+        &[
+            "read",
+            "light",
+            "--pin",
+            "27",
+            "--backend",
+            "simulated",
+            "--script",
+            "off:30,on:30",
+        ],
+        &[],
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "light: false\n");
+}
+
+#[test]
+fn read_calibrated() {
+    let dir = TempDir::new();
+    let calibration = dir.path().join("calibration.json");
+    std::fs::write(&calibration, r#"{"4": {"humidity": {"slope": 0}}}"#).unwrap();
+    let calibration = calibration.display().to_string();
+    let args = [
+        "--json",
+        "read",
+        "dht22",
+        "--pin",
+        "4",
+        "--backend",
+        "simulated",
+        "--calibration",
+        &calibration,
+    ];
+
+    let output = rpi_sensors(&args, &[]);
+    assert!(output.status.success());
+    let reading = json(&output);
+    assert_eq!(reading["humidity"], 0.0);
+    assert!(reading["raw"]["humidity"].is_f64(), "{reading}");
+
+    let output = rpi_sensors(&args[..args.len() - 1], &[]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn usage() {
+    let output = rpi_sensors(&["read", "dht22"], &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("--pin is missing"));
+}
+
+#[test]
+fn check_config() {
+    let output = rpi_sensors(&["--json", "check-config"], &config(1883));
+    assert!(output.status.success());
+    assert_eq!(json(&output)["backend"], "simulated");
+
+    let output = rpi_sensors(&["--json", "check-config"], &config(1883)[1..]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(json(&output)["message"], "MQTT_IP not set");
+}
+
+#[tokio::test]
+async fn publish_once() {
+    let broker = Broker::start();
+    let mut subscriber = Subscriber::connect(broker.port, TOPIC).await;
+
+    let output = rpi_sensors(&["--json", "publish-once"], &config(broker.port));
+    assert!(output.status.success());
+    assert_eq!(json(&output)[0]["topic"], TOPIC);
+
+    let publish = subscriber.next(WAIT).await.unwrap();
+    let data: Value = serde_json::from_slice(&publish.payload).unwrap();
+    assert!(data["temperature"].is_string(), "{data}");
+}
+
+#[tokio::test]
+async fn publish_once_calibrated() {
+    let broker = Broker::start();
+    let mut subscriber = Subscriber::connect(broker.port, TOPIC).await;
+    let dir = TempDir::new();
+    let calibration = dir.path().join("calibration.json");
+    std::fs::write(
+        &calibration,
+        r#"{"4": {"temperature": {"offset": 100}, "humidity": {"slope": 0}}}"#,
+    )
+    .unwrap();
+    let mut vars = config(broker.port);
+    vars.push((
+        "TEMPERATURE_CALIBRATION_FILE",
+        calibration.display().to_string(),
+    ));
+
+    let output = rpi_sensors(&["--json", "publish-once"], &vars);
+    assert!(output.status.success());
+    let message = &json(&output)[0];
+    assert_eq!(message["payload"]["humidity"], "0.0");
+    assert!(message["raw"]["humidity"].is_string(), "{message}");
+
+    let publish = subscriber.next(WAIT).await.unwrap();
+    let data: Value = serde_json::from_slice(&publish.payload).unwrap();
+    let temperature: f32 = data["temperature"].as_str().unwrap().parse().unwrap();
+    assert!(temperature > 50.0, "{data}");
+    let properties = publish.properties.unwrap();
+    assert!(properties
+        .user_properties
+        .iter()
+        .any(|(key, value)| key == "raw" && value.contains("humidity")));
+}
+
+#[tokio::test]
+async fn publish_once_failure() {
+    let broker = Broker::start();
+    let mut vars = config(broker.port);
+    vars.push(("TEMPERATURE_SIMULATION_TIMEOUT_RATE", "1".to_string()));
+
+    let output = rpi_sensors(&["--json", "publish-once"], &vars);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(json(&output)["error"], "timeout");
+}
+
+#[test]
+fn test_broker() {
+    let broker = Broker::start();
+    let output = rpi_sensors(&["--json", "test-broker"], &config(broker.port));
+    assert!(output.status.success());
+    assert!(json(&output)["round_trip_ms"].is_u64());
+
+    // Nothing listens on the port of a stopped listener.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let output = rpi_sensors(&["test-broker"], &config(port));
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,124 @@
+use serde_json::{json, Value};
+
+use std::time::Duration;
+
+/// A command received on the command topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Read the sensor and publish the result immediately.
+    ReadNow,
+
+    /// Change the delay between two scheduled readings.
+    SetInterval(Duration),
+
+    /// Stop the scheduled readings until `Resume` is received.
+    Pause,
+
+    /// Restart the scheduled readings.
+    Resume,
+
+    /// Read the configuration again and apply it.
+    Reload,
+}
+
+impl Command {
+    /// Parse a JSON payload such as `{"command": "set_interval", "seconds": 30}`.
+    ///
+    /// # Errors
+    /// Returns a message describing why the payload isn't a valid command.
+    pub fn parse(payload: &[u8]) -> Result<Self, String> {
+        let value: Value =
+            serde_json::from_slice(payload).map_err(|e| format!("invalid JSON: {e}"))?;
+        let name = value
+            .get("command")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "missing \"command\" field".to_string())?;
+
+        match name {
+            "read_now" => Ok(Self::ReadNow),
+            "set_interval" => {
+                let seconds = value
+                    .get("seconds")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| {
+                        "set_interval requires a positive \"seconds\" field".to_string()
+                    })?;
+                if seconds == 0 {
+                    return Err("set_interval requires a positive \"seconds\" field".to_string());
+                }
+                Ok(Self::SetInterval(Duration::from_secs(seconds)))
+            }
+            "pause" => Ok(Self::Pause),
+            "resume" => Ok(Self::Resume),
+            "reload" => Ok(Self::Reload),
+            _ => Err(format!("unknown command {name}")),
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::ReadNow => "read_now",
+            Self::SetInterval(_) => "set_interval",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Reload => "reload",
+        }
+    }
+}
+
+/// Build the payload sent back on the response topic.
+pub fn response(command: Option<Command>, result: Result<Value, String>) -> Value {
+    let name = command.map(Command::name);
+    match result {
+        Ok(data) => json!({
+            "command": name,
+            "status": "ok",
+            "data": data,
+        }),
+        Err(e) => json!({
+            "command": name,
+            "status": "error",
+            "error": e,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    use std::time::Duration;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            Command::parse(br#"{"command": "read_now"}"#),
+            Ok(Command::ReadNow)
+        );
+        assert_eq!(
+            Command::parse(br#"{"command": "set_interval", "seconds": 30}"#),
+            Ok(Command::SetInterval(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            Command::parse(br#"{"command": "pause"}"#),
+            Ok(Command::Pause)
+        );
+        assert_eq!(
+            Command::parse(br#"{"command": "resume"}"#),
+            Ok(Command::Resume)
+        );
+        assert_eq!(
+            Command::parse(br#"{"command": "reload"}"#),
+            Ok(Command::Reload)
+        );
+    }
+
+    #[test]
+    fn reject_invalid_commands() {
+        assert!(Command::parse(b"read_now").is_err());
+        assert!(Command::parse(br#"{"cmd": "read_now"}"#).is_err());
+        assert!(Command::parse(br#"{"command": "reboot"}"#).is_err());
+        assert!(Command::parse(br#"{"command": "set_interval"}"#).is_err());
+        assert!(Command::parse(br#"{"command": "set_interval", "seconds": 0}"#).is_err());
+    }
+}
@@ -0,0 +1,651 @@
+#[cfg(feature = "cdev")]
+use rpi_gpio::cdev::LineSpec;
+use rpi_gpio::{
+    bme280::Bme280, calibration::Calibrations, dht22::DecodeOptions, realtime::RealtimeOptions,
+    sht31::Sht31, simulated::SimulationOptions, status::REDACTED, Backend,
+};
+use rppal::i2c::I2c;
+use serde_json::{json, Value};
+
+use std::{
+    env::{self, VarError},
+    ffi::OsString,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+const SENSOR: &str = "TEMPERATURE_SENSOR";
+const IIO_DEVICE: &str = "TEMPERATURE_IIO_DEVICE";
+const I2C_BUS: &str = "TEMPERATURE_I2C_BUS";
+const I2C_ADDRESS: &str = "TEMPERATURE_I2C_ADDRESS";
+const GPIO_BACKEND: &str = "TEMPERATURE_GPIO_BACKEND";
+const DHT_PIN: &str = "TEMPERATURE_DHT_PIN";
+const DHT_LINE: &str = "TEMPERATURE_DHT_LINE";
+const DHT_RECOVERY: &str = "TEMPERATURE_DHT_RECOVERY";
+const DHT_REALTIME: &str = "TEMPERATURE_DHT_REALTIME";
+const DHT_REALTIME_CPU: &str = "TEMPERATURE_DHT_REALTIME_CPU";
+const DHT_REALTIME_PRIORITY: &str = "TEMPERATURE_DHT_REALTIME_PRIORITY";
+const CALIBRATION_FILE: &str = "TEMPERATURE_CALIBRATION_FILE";
+const SIMULATION_NOISE: &str = "TEMPERATURE_SIMULATION_NOISE";
+const SIMULATION_CHECKSUM_RATE: &str = "TEMPERATURE_SIMULATION_CHECKSUM_RATE";
+const SIMULATION_TIMEOUT_RATE: &str = "TEMPERATURE_SIMULATION_TIMEOUT_RATE";
+const MQTT_CLIENT_ID: &str = "TEMPERATURE_MQTT_CLIENT_ID";
+pub const MQTT_IP: &str = "MQTT_IP";
+pub const MQTT_PORT: &str = "MQTT_PORT";
+const MQTT_TOPIC: &str = "TEMPERATURE_MQTT_TOPIC";
+const MQTT_USERNAME: &str = "MQTT_USERNAME";
+const MQTT_PASSWORD: &str = "MQTT_PASSWORD";
+pub const MQTT_DELAY: &str = "TEMPERATURE_MQTT_DELAY";
+const MQTT_COMMAND_TOPIC: &str = "TEMPERATURE_MQTT_COMMAND_TOPIC";
+const CERTIFICATE_AUTHORITY_PATH: &str = "CERTIFICATE_AUTHORITY_PATH";
+const MTLS_CERT_PATH: &str = "MTLS_CERT_PATH";
+const MTLS_PKEY_PATH: &str = "MTLS_PKEY_PATH";
+const LOG_LEVEL: &str = "LOG_LEVEL";
+const HTTP_ADDR: &str = "TEMPERATURE_HTTP_ADDR";
+
+const ENV_FILE: &str = ".env";
+
+fn not_set(env: &str) -> String {
+    format!("{env} not set")
+}
+
+/// Where the variables are read from: the environment, possibly with the changes of an
+/// [`EnvFile`] on top.
+type Vars<'a> = dyn Fn(&str) -> Result<String, VarError> + 'a;
+
+fn required(var: &Vars, name: &str) -> Result<String, String> {
+    var(name).map_err(|_| not_set(name))
+}
+
+/// A fraction from 0 to 1, 0 if unset.
+fn rate(var: &Vars, name: &str) -> Result<f32, String> {
+    var(name).map_or(Ok(0.0), |value| {
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|rate| (0.0..=1.0).contains(rate))
+            .ok_or_else(|| format!("{name} is not between 0 and 1"))
+    })
+}
+
+/// Values of the environment before the `.env` file set them, by key.
+static ENV_FILE_KEYS: Mutex<Vec<(String, Option<OsString>)>> = Mutex::new(Vec::new());
+
+/// The changes a `.env` file makes to the environment, read first and only applied once the
+/// configuration they lead to is valid.
+struct EnvFile {
+    /// Value of each variable once applied, `None` to remove it.
+    changes: Vec<(String, Option<OsString>)>,
+    /// Variables set from the file for the first time, with the value they had before.
+    added: Vec<(String, Option<OsString>)>,
+    /// Variables removed from the file since the last load.
+    removed: Vec<String>,
+}
+
+impl EnvFile {
+    /// Read the `.env` file at `path`, if any, overriding the environment when `reload` is set.
+    /// The variables removed from the file since the last load get back the value they had
+    /// before it, if any.
+    fn read(path: &Path, reload: bool) -> Result<Self, dotenvy::Error> {
+        let entries = if path.exists() {
+            dotenvy::from_path_iter(path)?.collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let keys = ENV_FILE_KEYS.lock().unwrap();
+        let mut file = Self {
+            changes: Vec::new(),
+            added: Vec::new(),
+            removed: Vec::new(),
+        };
+        for (key, previous) in keys.iter() {
+            if !entries.iter().any(|(entry, _)| entry == key) {
+                file.removed.push(key.clone());
+                file.changes.push((key.clone(), previous.clone()));
+            }
+        }
+        for (key, value) in entries {
+            let known = keys.iter().any(|(loaded, _)| *loaded == key);
+            let previous = env::var_os(&key);
+            if known || reload || previous.is_none() {
+                if !known {
+                    file.added.push((key.clone(), previous));
+                }
+                file.changes.push((key, Some(value.into())));
+            }
+        }
+        drop(keys);
+        Ok(file)
+    }
+
+    /// The value of `name` once the file is applied.
+    fn var(&self, name: &str) -> Result<String, VarError> {
+        match self.changes.iter().find(|(key, _)| key == name) {
+            Some((_, Some(value))) => value.clone().into_string().map_err(VarError::NotUnicode),
+            Some((_, None)) => Err(VarError::NotPresent),
+            None => env::var(name),
+        }
+    }
+
+    /// Write the changes to the environment.
+    fn apply(self) {
+        let mut keys = ENV_FILE_KEYS.lock().unwrap();
+        keys.retain(|(key, _)| !self.removed.contains(key));
+        keys.extend(self.added);
+        drop(keys);
+        for (key, value) in self.changes {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+    }
+}
+
+/// How the sensor is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sensor {
+    /// Bit-banging from userspace.
+    #[default]
+    Dht22,
+    /// Through the `dht11` kernel driver.
+    Dht22Iio,
+    /// Temperature, humidity and pressure on I2C.
+    Bme280,
+    /// Temperature and humidity on I2C.
+    Sht31,
+    /// Temperature probes on the 1-Wire bus, published by ROM id.
+    Ds18b20,
+}
+
+impl FromStr for Sensor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dht22" => Ok(Self::Dht22),
+            "dht22-iio" => Ok(Self::Dht22Iio),
+            "bme280" => Ok(Self::Bme280),
+            "sht31" => Ok(Self::Sht31),
+            "ds18b20" => Ok(Self::Ds18b20),
+            _ => Err(format!(
+                "unknown sensor {s}, expected dht22, dht22-iio, bme280, sht31 or ds18b20"
+            )),
+        }
+    }
+}
+
+impl Sensor {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Dht22 => "dht22",
+            Self::Dht22Iio => "dht22-iio",
+            Self::Bme280 => "bme280",
+            Self::Sht31 => "sht31",
+            Self::Ds18b20 => "ds18b20",
+        }
+    }
+
+    /// Model published in the message properties.
+    #[must_use]
+    pub const fn model(self) -> &'static str {
+        match self {
+            Self::Dht22 | Self::Dht22Iio => "DHT22",
+            Self::Bme280 => "BME280",
+            Self::Sht31 => "SHT31",
+            Self::Ds18b20 => "DS18B20",
+        }
+    }
+
+    /// Address of the I2C sensors when it isn't set.
+    const fn default_address(self) -> Option<u8> {
+        match self {
+            Self::Bme280 => Some(Bme280::<I2c>::DEFAULT_ADDRESS),
+            Self::Sht31 => Some(Sht31::<I2c>::DEFAULT_ADDRESS),
+            Self::Dht22 | Self::Dht22Iio | Self::Ds18b20 => None,
+        }
+    }
+}
+
+/// When the captures run in real-time mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Realtime {
+    #[default]
+    Off,
+    On,
+    /// Every other capture, to compare the failure rates of both modes.
+    Compare,
+}
+
+impl FromStr for Realtime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "on" => Ok(Self::On),
+            "compare" => Ok(Self::Compare),
+            _ => Err(format!("unknown mode {s}, expected off, on or compare")),
+        }
+    }
+}
+
+impl Realtime {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::On => "on",
+            Self::Compare => "compare",
+        }
+    }
+}
+
+/// Settings of the temperature service, read from the environment and the `.env` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub client_id: String,
+    pub mqtt_ip: String,
+    pub mqtt_port: u16,
+    pub mqtt_topic: String,
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+    pub mqtt_command_topic: Option<String>,
+    pub delay: Duration,
+    pub sensor: Sensor,
+    /// IIO device of the `dht22-iio` sensor, found by driver name if unset.
+    pub iio_device: Option<String>,
+    /// I2C bus and address of the `bme280` and `sht31` sensors.
+    pub i2c_bus: u8,
+    pub i2c_address: Option<u8>,
+    pub backend: Backend,
+    /// Pin of the `dht22` sensor, only required for it.
+    pub pin: Option<u8>,
+    /// Line of the `cdev` backend, the offset `pin` of `gpiochip0` if unset.
+    pub line: Option<String>,
+    /// Heuristics allowed to recover captures with a wrong checksum.
+    pub recovery: DecodeOptions,
+    pub realtime: Realtime,
+    pub realtime_options: RealtimeOptions,
+    /// File of the calibrations, see [`rpi_gpio::calibration`].
+    pub calibration_file: Option<String>,
+    /// Calibration of each sensor, by pin, I2C bus and address, IIO device or ROM id.
+    pub calibrations: Calibrations,
+    /// Readings of the `simulated` backend.
+    pub simulation: SimulationOptions,
+    pub ca_cert_path: Option<String>,
+    pub mtls_cert_path: Option<String>,
+    pub mtls_pkey_path: Option<String>,
+    pub log_level: String,
+    /// Address of the status endpoint, only read at startup.
+    pub http_addr: Option<String>,
+}
+
+impl Config {
+    /// Load the `.env` file, if any, and read the configuration.
+    ///
+    /// When `reload` is set, the values of the `.env` file override the ones already in the
+    /// environment, so that an edited file takes effect. The environment is left as it was if the
+    /// configuration is invalid.
+    ///
+    /// # Errors
+    /// Returns a message if a variable is missing or invalid.
+    pub fn load(reload: bool) -> Result<Self, String> {
+        let file = EnvFile::read(Path::new(ENV_FILE), reload)
+            .map_err(|e| format!("Can't load {ENV_FILE}: {e}"))?;
+        let config = Self::from_vars(&|name| file.var(name))?;
+        file.apply();
+        Ok(config)
+    }
+
+    /// # Errors
+    /// Returns a message if a variable is missing or invalid.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(&|name| env::var(name))
+    }
+
+    fn from_vars(var: &Vars) -> Result<Self, String> {
+        let sensor = var(SENSOR)
+            .map_or_else(|_| Ok(Sensor::default()), |value| value.parse::<Sensor>())
+            .map_err(|e| format!("{SENSOR} is invalid: {e}"))?;
+        let calibration_file = var(CALIBRATION_FILE).ok();
+        let calibrations = calibration_file
+            .as_deref()
+            .map_or_else(|| Ok(Calibrations::default()), Calibrations::load)
+            .map_err(|e| format!("{CALIBRATION_FILE} is invalid: {e}"))?;
+        let config = Self {
+            client_id: format!("{}-rust", required(var, MQTT_CLIENT_ID)?),
+            mqtt_ip: required(var, MQTT_IP)?,
+            mqtt_port: required(var, MQTT_PORT)?
+                .parse::<u16>()
+                .map_err(|_| format!("{MQTT_PORT} is not a valid u16"))?,
+            mqtt_topic: required(var, MQTT_TOPIC)?,
+            mqtt_username: required(var, MQTT_USERNAME)?,
+            mqtt_password: required(var, MQTT_PASSWORD)?,
+            mqtt_command_topic: var(MQTT_COMMAND_TOPIC).ok(),
+            delay: Duration::from_secs(
+                required(var, MQTT_DELAY)?
+                    .parse::<u64>()
+                    .map_err(|_| format!("{MQTT_DELAY} is not a valid u64"))?,
+            ),
+            sensor,
+            iio_device: var(IIO_DEVICE).ok(),
+            i2c_bus: var(I2C_BUS).map_or(Ok(1), |value| {
+                value
+                    .parse::<u8>()
+                    .map_err(|_| format!("{I2C_BUS} is not a valid u8"))
+            })?,
+            i2c_address: var(I2C_ADDRESS).map_or_else(
+                |_| Ok(sensor.default_address()),
+                |value| {
+                    u8::from_str_radix(value.trim_start_matches("0x"), 16)
+                        .map(Some)
+                        .map_err(|_| format!("{I2C_ADDRESS} is not a valid address"))
+                },
+            )?,
+            backend: var(GPIO_BACKEND)
+                .map_or_else(|_| Ok(Backend::default()), |value| value.parse())
+                .map_err(|e| format!("{GPIO_BACKEND} is invalid: {e}"))?,
+            pin: var(DHT_PIN)
+                .ok()
+                .map(|value| value.parse::<u8>())
+                .transpose()
+                .map_err(|_| format!("{DHT_PIN} is not a valid u8"))?,
+            line: var(DHT_LINE).ok(),
+            recovery: var(DHT_RECOVERY)
+                .map_or_else(|_| Ok(DecodeOptions::default()), |value| value.parse())
+                .map_err(|e| format!("{DHT_RECOVERY} is invalid: {e}"))?,
+            realtime: var(DHT_REALTIME)
+                .map_or_else(|_| Ok(Realtime::default()), |value| value.parse())
+                .map_err(|e| format!("{DHT_REALTIME} is invalid: {e}"))?,
+            realtime_options: RealtimeOptions {
+                priority: var(DHT_REALTIME_PRIORITY).map_or(Ok(50), |value| {
+                    value
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|priority| (1..=99).contains(priority))
+                        .ok_or_else(|| format!("{DHT_REALTIME_PRIORITY} is not between 1 and 99"))
+                })?,
+                cpu: var(DHT_REALTIME_CPU)
+                    .ok()
+                    .map(|value| {
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| format!("{DHT_REALTIME_CPU} is not a valid usize"))
+                    })
+                    .transpose()?,
+            },
+            calibration_file,
+            calibrations,
+            simulation: SimulationOptions {
+                noise: var(SIMULATION_NOISE).map_or(Ok(0.2), |value| {
+                    value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|noise| *noise >= 0.0)
+                        .ok_or_else(|| format!("{SIMULATION_NOISE} is not a valid noise"))
+                })?,
+                checksum_failure_rate: rate(var, SIMULATION_CHECKSUM_RATE)?,
+                timeout_failure_rate: rate(var, SIMULATION_TIMEOUT_RATE)?,
+                ..SimulationOptions::default()
+            },
+            ca_cert_path: var(CERTIFICATE_AUTHORITY_PATH).ok(),
+            mtls_cert_path: var(MTLS_CERT_PATH).ok(),
+            mtls_pkey_path: var(MTLS_PKEY_PATH).ok(),
+            log_level: var(LOG_LEVEL).unwrap_or_else(|_| "info".to_string()),
+            http_addr: var(HTTP_ADDR).ok(),
+        };
+        if config.sensor == Sensor::Dht22 && config.pin.is_none() {
+            return Err(not_set(DHT_PIN));
+        }
+        #[cfg(feature = "cdev")]
+        if config.sensor == Sensor::Dht22 {
+            config
+                .line_spec()
+                .map_err(|e| format!("{DHT_LINE} is invalid: {e}"))?;
+        }
+        Ok(config)
+    }
+
+    /// The line of the `cdev` backend.
+    ///
+    /// # Errors
+    /// Returns a message if `line` is invalid, or if neither `line` nor `pin` is set.
+    #[cfg(feature = "cdev")]
+    pub fn line_spec(&self) -> Result<LineSpec, String> {
+        match (&self.line, self.pin) {
+            (Some(line), _) => line.parse(),
+            (None, Some(pin)) => Ok(LineSpec::offset(u32::from(pin))),
+            (None, None) => Err(not_set(DHT_PIN)),
+        }
+    }
+
+    /// The configuration with secrets redacted, for the status endpoint.
+    #[must_use]
+    pub fn summary(&self) -> Value {
+        let mut summary = json!({
+            "client_id": self.client_id,
+            "mqtt_ip": self.mqtt_ip,
+            "mqtt_port": self.mqtt_port,
+            "mqtt_topic": self.mqtt_topic,
+            "mqtt_username": self.mqtt_username,
+            "mqtt_password": REDACTED,
+            "mqtt_command_topic": self.mqtt_command_topic,
+            "delay": self.delay.as_secs(),
+            "sensor": self.sensor.name(),
+            "iio_device": self.iio_device,
+            "i2c_bus": self.i2c_bus,
+            "i2c_address": self.i2c_address.map(|address| format!("{address:#04x}")),
+            "backend": self.backend.name(),
+            "pin": self.pin,
+            "line": self.line,
+            "recovery": {
+                "clustering": self.recovery.clustering,
+                "realign": self.recovery.realign,
+                "bit_flip": self.recovery.bit_flip,
+            },
+            "realtime": {
+                "mode": self.realtime.name(),
+                "priority": self.realtime_options.priority,
+                "cpu": self.realtime_options.cpu,
+            },
+            "calibration": self.calibration_file,
+            "tls": self.ca_cert_path.is_some(),
+            "mtls": self.mtls_cert_path.is_some() && self.mtls_pkey_path.is_some(),
+            "log_level": self.log_level,
+        });
+        if self.backend == Backend::Simulated {
+            summary["simulation"] = json!({
+                "noise": self.simulation.noise,
+                "checksum_failure_rate": self.simulation.checksum_failure_rate,
+                "timeout_failure_rate": self.simulation.timeout_failure_rate,
+            });
+        }
+        summary
+    }
+
+    /// The real-time settings of the capture number `capture`, counted since the service
+    /// started, `None` to capture normally.
+    #[must_use]
+    pub const fn realtime_for(&self, capture: u32) -> Option<RealtimeOptions> {
+        match self.realtime {
+            Realtime::On => Some(self.realtime_options),
+            Realtime::Compare if capture % 2 == 0 => Some(self.realtime_options),
+            Realtime::Off | Realtime::Compare => None,
+        }
+    }
+
+    /// Whether switching from `self` to `other` requires a new connection to the broker. The
+    /// topic counts, since the last will is set on its availability topic when connecting.
+    #[must_use]
+    pub fn needs_reconnect(&self, other: &Self) -> bool {
+        self.client_id != other.client_id
+            || self.mqtt_topic != other.mqtt_topic
+            || self.mqtt_ip != other.mqtt_ip
+            || self.mqtt_port != other.mqtt_port
+            || self.mqtt_username != other.mqtt_username
+            || self.mqtt_password != other.mqtt_password
+            || self.tls_changed(other)
+    }
+
+    /// Whether the certificates have to be loaded again.
+    #[must_use]
+    pub fn tls_changed(&self, other: &Self) -> bool {
+        self.ca_cert_path != other.ca_cert_path
+            || self.mtls_cert_path != other.mtls_cert_path
+            || self.mtls_pkey_path != other.mtls_pkey_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, EnvFile, Realtime, Sensor};
+    use rpi_gpio::{
+        calibration::Calibrations, dht22::DecodeOptions, realtime::RealtimeOptions,
+        simulated::SimulationOptions, Backend,
+    };
+
+    use std::{env, fs, process, time::Duration};
+
+    fn config() -> Config {
+        Config {
+            client_id: "temperature-rust".to_string(),
+            mqtt_ip: "127.0.0.1".to_string(),
+            mqtt_port: 1883,
+            mqtt_topic: "home/temperature".to_string(),
+            mqtt_username: "user".to_string(),
+            mqtt_password: "password".to_string(),
+            mqtt_command_topic: None,
+            delay: Duration::from_secs(60),
+            sensor: Sensor::Dht22,
+            iio_device: None,
+            i2c_bus: 1,
+            i2c_address: None,
+            backend: Backend::Rppal,
+            pin: Some(4),
+            line: None,
+            recovery: DecodeOptions::default(),
+            realtime: Realtime::Off,
+            realtime_options: RealtimeOptions::default(),
+            calibration_file: None,
+            calibrations: Calibrations::default(),
+            simulation: SimulationOptions::default(),
+            ca_cert_path: None,
+            mtls_cert_path: None,
+            mtls_pkey_path: None,
+            log_level: "info".to_string(),
+            http_addr: None,
+        }
+    }
+
+    #[test]
+    fn live_changes_keep_the_connection() {
+        let old = config();
+        let new = Config {
+            mqtt_command_topic: Some("home/temperature/command".to_string()),
+            delay: Duration::from_secs(30),
+            sensor: Sensor::Dht22Iio,
+            log_level: "debug".to_string(),
+            ..config()
+        };
+        assert!(!old.needs_reconnect(&new));
+    }
+
+    #[test]
+    fn simulation_summary() {
+        assert!(config().summary().get("simulation").is_none());
+        let simulated = Config {
+            backend: Backend::Simulated,
+            ..config()
+        };
+        assert_eq!(simulated.summary()["backend"], "simulated");
+        assert_eq!(
+            simulated.summary()["simulation"]["timeout_failure_rate"],
+            0.0
+        );
+    }
+
+    #[test]
+    fn summary_redacts_password() {
+        let summary = config().summary();
+        assert_eq!(summary["mqtt_username"], "user");
+        assert!(!summary.to_string().contains("\"password\""));
+    }
+
+    #[test]
+    fn realtime_compare_alternates() {
+        let compare = Config {
+            realtime: Realtime::Compare,
+            ..config()
+        };
+        assert_eq!(compare.realtime_for(1), None);
+        assert_eq!(compare.realtime_for(2), Some(RealtimeOptions::default()));
+        assert_eq!(compare.realtime_for(3), None);
+        assert_eq!(config().realtime_for(2), None);
+        assert!("always".parse::<Realtime>().is_err());
+    }
+
+    #[test]
+    fn i2c_sensors() {
+        let sensor = "bme280".parse::<Sensor>().unwrap();
+        assert_eq!(sensor.model(), "BME280");
+        assert_eq!(sensor.default_address(), Some(0x76));
+        assert_eq!(Sensor::Sht31.default_address(), Some(0x44));
+        assert_eq!(Sensor::Dht22Iio.model(), "DHT22");
+    }
+
+    #[test]
+    fn connection_changes_reconnect() {
+        let old = config();
+        assert!(old.needs_reconnect(&Config {
+            mqtt_password: "new password".to_string(),
+            ..config()
+        }));
+        assert!(old.needs_reconnect(&Config {
+            ca_cert_path: Some("/etc/ssl/ca.pem".to_string()),
+            ..config()
+        }));
+        assert!(old.needs_reconnect(&Config {
+            mqtt_topic: "home/office/temperature".to_string(),
+            ..config()
+        }));
+    }
+
+    #[test]
+    fn reload_removes_deleted_keys() {
+        let path = env::temp_dir().join(format!("temperature-{}.env", process::id()));
+        env::set_var("RELOAD_TEST_KEPT", "environment");
+        fs::write(
+            &path,
+            "RELOAD_TEST_KEPT=file\nRELOAD_TEST_REMOVED=file\nRELOAD_TEST_OPTIONAL=file\n",
+        )
+        .unwrap();
+        EnvFile::read(&path, false).unwrap().apply();
+        assert_eq!(env::var("RELOAD_TEST_KEPT").unwrap(), "environment");
+        assert_eq!(env::var("RELOAD_TEST_REMOVED").unwrap(), "file");
+
+        fs::write(
+            &path,
+            "RELOAD_TEST_KEPT=edited\nRELOAD_TEST_OPTIONAL=edited\n",
+        )
+        .unwrap();
+        let file = EnvFile::read(&path, true).unwrap();
+        // Nothing changes until the file is applied.
+        assert_eq!(file.var("RELOAD_TEST_KEPT").unwrap(), "edited");
+        assert!(file.var("RELOAD_TEST_REMOVED").is_err());
+        assert_eq!(env::var("RELOAD_TEST_KEPT").unwrap(), "environment");
+        assert_eq!(env::var("RELOAD_TEST_REMOVED").unwrap(), "file");
+        file.apply();
+        assert_eq!(env::var("RELOAD_TEST_KEPT").unwrap(), "edited");
+        assert_eq!(env::var("RELOAD_TEST_OPTIONAL").unwrap(), "edited");
+        assert!(env::var("RELOAD_TEST_REMOVED").is_err());
+
+        fs::remove_file(&path).unwrap();
+        EnvFile::read(&path, true).unwrap().apply();
+        assert_eq!(env::var("RELOAD_TEST_KEPT").unwrap(), "environment");
+        assert!(env::var("RELOAD_TEST_OPTIONAL").is_err());
+    }
+}
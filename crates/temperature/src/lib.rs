@@ -0,0 +1,3 @@
+//! Configuration and readings of the temperature service, shared with the `rpi-sensors` CLI.
+pub mod config;
+pub mod reading;
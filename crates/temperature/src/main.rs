@@ -1,111 +1,321 @@
-use rpi_gpio::{dht22::read, tls::load_certs, ReadingError};
+mod command;
+mod replay;
+
+use command::{response, Command};
+use rpi_gpio::{
+    http::{serve, Response},
+    metrics::Metrics,
+    realtime,
+    record::Recorder,
+    status::Status,
+    tls::load_certs,
+};
 use rumqttc::{
-    v5::{mqttbytes::QoS, AsyncClient, Event, MqttOptions},
-    Transport,
+    v5::{
+        mqttbytes::{
+            v5::{LastWill, Packet, Publish, PublishProperties},
+            QoS,
+        },
+        AsyncClient, ClientError, Event, MqttOptions,
+    },
+    TlsConfiguration, Transport,
+};
+use serde_json::{json, Value};
+use temperature::{
+    config::{Config, Realtime, MQTT_DELAY, MQTT_IP, MQTT_PORT},
+    reading::{publish_properties, read_sensor, topic, OpenedSensor, Reporting},
 };
-use serde_json::json;
-use tokio::time::sleep;
-use tracing::{debug, error, info, level_filters::LevelFilter, trace};
-use tracing_subscriber::EnvFilter;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+    time::{sleep, sleep_until, timeout, Instant},
+};
+use tracing::{debug, error, info, level_filters::LevelFilter, trace, warn};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+use std::{env, error::Error, future::pending, process::exit, sync::Arc, time::Duration};
+
+type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Payloads of the availability topic, the offline one being the last will.
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+const USAGE: &str = "Usage: temperature [--record <file>]
+       temperature replay <file>...";
+
+/// What woke the main loop up.
+enum Wake {
+    Read,
+    Request(Option<Box<Publish>>),
+    Hangup,
+}
+
+/// Read the sensor and publish the result.
+///
+/// `read_attempts` counts the attempts since the last published reading, `captures` every
+/// attempt since the service started, alternating the capture modes when comparing them.
+///
+/// The outer error is a publishing failure, the inner one a reading failure.
+async fn read_and_publish(
+    client: &AsyncClient,
+    reporting: &Reporting,
+    config: &Config,
+    sensor: &mut OpenedSensor,
+    read_attempts: &mut u32,
+    captures: &mut u32,
+    delay: Duration,
+) -> Result<Result<Value, String>, ClientError> {
+    debug!("Getting temperature and humidity...");
+    *read_attempts = read_attempts.saturating_add(1);
+    *captures = captures.wrapping_add(1);
+    let realtime = config.realtime_for(*captures);
+    match read_sensor(config, realtime, reporting, sensor).await {
+        Ok(measured) => {
+            let recovery = measured.recovery();
+            if let Some(recovery) = recovery {
+                warn!("Capture recovered with: {recovery}");
+            }
+            let messages = measured.messages();
+            let data = match messages.as_slice() {
+                [message] if message.id.is_none() => message.data(),
+                _ => Value::Object(
+                    messages
+                        .iter()
+                        .map(|message| (message.id.clone().unwrap_or_default(), message.data()))
+                        .collect(),
+                ),
+            };
+            debug!("{data}");
+            reporting.status.lock().unwrap().reading(data.clone());
+            let properties = publish_properties(config, *read_attempts, delay, recovery);
+            *read_attempts = 0;
+            for message in messages {
+                let topic = topic(config, message.id.as_deref());
+                let published = client
+                    .publish_with_properties(
+                        topic,
+                        QoS::AtLeastOnce,
+                        false,
+                        message.payload.to_string(),
+                        message.properties(&properties),
+                    )
+                    .await;
+                reporting.metrics.published(published.is_ok());
+                published?;
+            }
+            debug!("Data published!");
+            Ok(Ok(data))
+        }
+        Err(e) => {
+            error!("Failed to read temperature and humidity: {}", e);
+            trace!("{:?}", e);
+            reporting.status.lock().unwrap().failure();
+            Ok(Err(e.to_string()))
+        }
+    }
+}
+
+/// Send `payload` to the response topic of `request`, if it has one.
+async fn reply(client: &AsyncClient, request: &Publish, payload: &Value) {
+    let Some(properties) = &request.properties else {
+        debug!("Command has no properties, not replying");
+        return;
+    };
+    let Some(response_topic) = &properties.response_topic else {
+        debug!("Command has no response topic, not replying");
+        return;
+    };
+
+    let response_properties = PublishProperties {
+        payload_format_indicator: Some(1),
+        content_type: Some("application/json".to_string()),
+        correlation_data: properties.correlation_data.clone(),
+        ..Default::default()
+    };
+    if let Err(e) = client
+        .publish_with_properties(
+            response_topic,
+            QoS::AtLeastOnce,
+            false,
+            payload.to_string(),
+            response_properties,
+        )
+        .await
+    {
+        error!("Failed to publish command response: {}", e);
+    }
+}
 
-use std::{env, error::Error, path::Path, time::Duration};
+fn log_filter(log_level: &str) -> Result<EnvFilter, Box<dyn Error>> {
+    Ok(EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env()?
+        .add_directive(format!("rpi_gpio={log_level}").parse()?)
+        .add_directive(format!("temperature={log_level}").parse()?))
+}
+
+fn tls_config(config: &Config) -> Result<Option<TlsConfiguration>, Box<dyn Error>> {
+    load_certs(
+        config.ca_cert_path.clone(),
+        config.mtls_pkey_path.clone(),
+        config.mtls_cert_path.clone(),
+    )
+}
+
+/// Read the configuration again and apply the changes that don't need a new connection.
+///
+/// Nothing changes unless the whole configuration is valid. Returns whether the connection
+/// settings changed, in which case the caller has to reconnect.
+async fn reload_config(
+    client: &AsyncClient,
+    config: &mut Config,
+    client_config: &mut Option<TlsConfiguration>,
+    log_handle: &LogHandle,
+) -> Result<bool, Box<dyn Error>> {
+    let new_config = Config::load(true)?;
+    let new_client_config = if new_config.tls_changed(config) {
+        Some(tls_config(&new_config)?)
+    } else {
+        None
+    };
+    let new_filter = if new_config.log_level == config.log_level {
+        None
+    } else {
+        Some(log_filter(&new_config.log_level)?)
+    };
+
+    let reconnect = config.needs_reconnect(&new_config);
+    if !reconnect && new_config.mqtt_command_topic != config.mqtt_command_topic {
+        if let Some(topic) = &config.mqtt_command_topic {
+            client.unsubscribe(topic).await?;
+        }
+        if let Some(topic) = &new_config.mqtt_command_topic {
+            info!("Listening for commands on {topic}");
+            client.subscribe(topic, QoS::AtLeastOnce).await?;
+        }
+    }
+    if let Some(filter) = new_filter {
+        log_handle.reload(filter)?;
+        info!("Using log level: {}", new_config.log_level);
+    }
 
-fn not_set(env: &str) -> String {
-    format!("{env} not set")
+    if let Some(new_client_config) = new_client_config {
+        *client_config = new_client_config;
+    }
+    *config = new_config;
+    Ok(reconnect)
 }
 
-const LIGHT_PIN: &str = "TEMPERATURE_DHT_PIN";
-const MQTT_CLIENT_ID: &str = "TEMPERATURE_MQTT_CLIENT_ID";
-const MQTT_IP: &str = "MQTT_IP";
-const MQTT_PORT: &str = "MQTT_PORT";
-const MQTT_TOPIC: &str = "TEMPERATURE_MQTT_TOPIC";
-const MQTT_USERNAME: &str = "MQTT_USERNAME";
-const MQTT_PASSWORD: &str = "MQTT_PASSWORD";
-const MQTT_DELAY: &str = "TEMPERATURE_MQTT_DELAY";
-const CERTIFICATE_AUTHORITY_PATH: &str = "CERTIFICATE_AUTHORITY_PATH";
-const MTLS_CERT_PATH: &str = "MTLS_CERT_PATH";
-const MTLS_PKEY_PATH: &str = "MTLS_PKEY_PATH";
-
-fn read_temperature_and_humidity(dht_pin: u8) -> Result<(String, String), ReadingError> {
-    match read(dht_pin) {
-        Ok(reading) => {
-            let temperature = format!("{:.1}", reading.temperature);
-            let humidity = format!("{:.1}", reading.humidity);
-            Ok((temperature, humidity))
+/// Lock the memory once the real-time captures are enabled, so that page faults can't delay
+/// them.
+fn lock_memory(config: &Config) {
+    if config.realtime != Realtime::Off {
+        if let Err(e) = realtime::lock_memory() {
+            warn!("Failed to lock the memory: {}", e);
         }
-        Err(e) => Err(e),
     }
-    // // When debugging
-    // Ok((10.0.to_string(), 10.0.to_string()))
+}
+
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
 }
 
 #[allow(clippy::too_many_lines)]
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let path = Path::new(".env");
-    if path.exists() {
-        dotenvy::from_path(path).unwrap();
-    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    let record_path = match args.as_slice() {
+        [] => None,
+        [command, paths @ ..] if command == "replay" && !paths.is_empty() => {
+            return replay::run(paths);
+        }
+        [flag, path] if flag == "--record" => Some(path.clone()),
+        _ => {
+            eprintln!("{USAGE}");
+            exit(2);
+        }
+    };
 
-    let client_id = format!(
-        "{}-rust",
-        env::var(MQTT_CLIENT_ID).unwrap_or_else(|_| panic!("{}", not_set(MQTT_CLIENT_ID)))
-    );
-    let mqtt_ip = env::var(MQTT_IP).unwrap_or_else(|_| panic!("{}", not_set(MQTT_IP)));
-    let mqtt_port = env::var(MQTT_PORT)
-        .unwrap_or_else(|_| panic!("{}", not_set(MQTT_PORT)))
-        .parse::<u16>()
-        .unwrap_or_else(|_| panic!("{MQTT_PORT} is not a valid u16"));
-    let mqtt_topic = env::var(MQTT_TOPIC).unwrap_or_else(|_| panic!("{}", not_set(MQTT_TOPIC)));
-    let mqtt_username =
-        env::var(MQTT_USERNAME).unwrap_or_else(|_| panic!("{}", not_set(MQTT_USERNAME)));
-    let mqtt_password =
-        env::var(MQTT_PASSWORD).unwrap_or_else(|_| panic!("{}", not_set(MQTT_PASSWORD)));
-    let mqtt_delay = env::var(MQTT_DELAY)
-        .unwrap_or_else(|_| panic!("{}", not_set(MQTT_DELAY)))
-        .parse::<u64>()
-        .unwrap_or_else(|_| panic!("{MQTT_PORT} is not a valid u64"));
-    let pin = env::var(LIGHT_PIN)
-        .unwrap_or_else(|_| panic!("{}", not_set(LIGHT_PIN)))
-        .parse::<u8>()
-        .unwrap_or_else(|_| panic!("{LIGHT_PIN} is not a valid u16"));
-    let ca_cert_path: Option<String> = env::var(CERTIFICATE_AUTHORITY_PATH).ok();
-    let mtls_cert_path: Option<String> = env::var(MTLS_CERT_PATH).ok();
-    let mtls_pkey_path: Option<String> = env::var(MTLS_PKEY_PATH).ok();
-
-    let log_level_str = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
-
-    println!("Using log level: {log_level_str}");
-
-    let filter = EnvFilter::builder()
-        .with_default_directive(LevelFilter::INFO.into())
-        .from_env()
-        .unwrap()
-        .add_directive(format!("rpi_gpio={log_level_str}").parse().unwrap())
-        .add_directive(format!("temperature={log_level_str}").parse().unwrap());
-
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .compact()
+    let mut config = Config::load(false).unwrap_or_else(|e| panic!("{e}"));
+
+    println!("Using log level: {}", config.log_level);
+
+    let (filter, log_handle) = reload::Layer::new(log_filter(&config.log_level).unwrap());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().compact())
         .init();
 
-    trace!("{MQTT_PORT}: {mqtt_port}");
-    trace!("{MQTT_DELAY}: {mqtt_delay}");
-    trace!("{MQTT_IP}: {mqtt_ip}");
+    trace!("{MQTT_PORT}: {}", config.mqtt_port);
+    trace!("{MQTT_DELAY}: {}", config.delay.as_secs());
+    trace!("{MQTT_IP}: {}", config.mqtt_ip);
 
-    let delay = Duration::from_secs(mqtt_delay);
+    let mut delay = config.delay;
+    let mut paused = false;
     let err_read_delay = Duration::from_secs(10);
 
-    let client_config = load_certs(ca_cert_path, mtls_pkey_path, mtls_cert_path).unwrap();
+    let status = Status::shared(config.summary());
+    let metrics = Arc::new(Metrics::new());
+    if let Some(addr) = config.http_addr.clone() {
+        let status = status.clone();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let handler = move |path: &str| match path {
+                "/status" => Some(Response::json(&status.lock().unwrap().to_json())),
+                "/metrics" => Some(Response::prometheus(metrics.render())),
+                _ => None,
+            };
+            if let Err(e) = serve(&addr, handler).await {
+                error!("Status endpoint stopped: {}", e);
+            }
+        });
+    }
+
+    let recorder = record_path.map(|path| {
+        info!("Recording captures to {path}");
+        Recorder::open(&path).unwrap_or_else(|e| panic!("Can't open {path}: {e}"))
+    });
+    let reporting = Reporting {
+        status: status.clone(),
+        metrics: metrics.clone(),
+        recorder,
+    };
+
+    lock_memory(&config);
+    let mut captures: u32 = 0;
+    let mut sensor = OpenedSensor::default();
+    let mut client_config = tls_config(&config).unwrap();
+    let mut hangup = signal(SignalKind::hangup())?;
 
+    let mut first_connection = true;
     loop {
+        if first_connection {
+            first_connection = false;
+        } else {
+            metrics.reconnected();
+        }
         info!("Connecting to MQTT broker...");
 
-        let mut mqttoptions = MqttOptions::new(&client_id, &mqtt_ip, mqtt_port);
+        let availability_topic = format!("{}/availability", config.mqtt_topic);
+        let mut mqttoptions =
+            MqttOptions::new(&config.client_id, &config.mqtt_ip, config.mqtt_port);
         mqttoptions
             .set_keep_alive(Duration::from_secs(60))
             .set_clean_start(true)
-            .set_credentials(&mqtt_username, &mqtt_password);
+            .set_credentials(&config.mqtt_username, &config.mqtt_password)
+            .set_last_will(LastWill::new(
+                &availability_topic,
+                OFFLINE,
+                QoS::AtLeastOnce,
+                true,
+                None,
+            ));
 
         if let Some(config) = &client_config {
             info!("Using TLS");
@@ -114,9 +324,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         let (client, mut eventloop) = AsyncClient::new(mqttoptions, 50);
 
-        let event_loop_handle = tokio::spawn(async move {
+        let (command_tx, mut command_rx) = mpsc::unbounded_channel::<Publish>();
+        let event_status = status.clone();
+        let mut event_loop_handle = tokio::spawn(async move {
             loop {
-                match eventloop.poll().await {
+                let event = eventloop.poll().await;
+                // Connected once the broker accepted the connection, not on the first poll.
+                let connected = match &event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => Some(true),
+                    Ok(_) => None,
+                    Err(_) => Some(false),
+                };
+                event_status.lock().unwrap().connection(
+                    connected,
+                    eventloop.pending.len() + usize::from(eventloop.state.inflight()),
+                );
+                match event {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if command_tx.send(publish).is_err() {
+                            break;
+                        }
+                    }
                     Ok(Event::Outgoing(_) | Event::Incoming(_)) => {}
                     Err(e) => {
                         error!("Error in event loop: {:?}", e);
@@ -126,34 +354,174 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         });
 
+        if let Err(e) = client
+            .publish(&availability_topic, QoS::AtLeastOnce, true, ONLINE)
+            .await
+        {
+            error!("Failed to publish the availability: {}", e);
+        }
+
+        if let Some(topic) = &config.mqtt_command_topic {
+            info!("Listening for commands on {topic}");
+            if let Err(e) = client.subscribe(topic, QoS::AtLeastOnce).await {
+                error!("Failed to subscribe to the command topic: {}", e);
+            }
+        }
+
+        let mut read_attempts: u32 = 0;
+        let mut next_read = if paused { None } else { Some(Instant::now()) };
+        let mut reconnect = false;
         loop {
-            debug!("Getting temperature and humidity...");
-            match read_temperature_and_humidity(pin) {
-                Ok((temperature, humidity)) => {
-                    let data = json!({
-                        "temperature": temperature,
-                        "humidity": humidity,
-                    });
-                    debug!("temp: {temperature}, humidity: {humidity}");
-                    match client
-                        .publish(&mqtt_topic, QoS::AtLeastOnce, false, data.to_string())
-                        .await
-                    {
-                        Ok(()) => {
-                            debug!("Data published!");
+            let wake = tokio::select! {
+                () = wait_until(next_read) => Wake::Read,
+                request = command_rx.recv() => Wake::Request(request.map(Box::new)),
+                _ = hangup.recv() => Wake::Hangup,
+            };
+
+            let (command, request) = match wake {
+                Wake::Read => {
+                    let result = read_and_publish(
+                        &client,
+                        &reporting,
+                        &config,
+                        &mut sensor,
+                        &mut read_attempts,
+                        &mut captures,
+                        delay,
+                    )
+                    .await;
+                    match result {
+                        Ok(Ok(_)) => next_read = Some(Instant::now() + delay),
+                        Ok(Err(_)) => next_read = Some(Instant::now() + err_read_delay),
+                        Err(e) => {
+                            error!("Failed to publish data: {}", e);
+                            break;
                         }
+                    }
+                    continue;
+                }
+                Wake::Request(None) => {
+                    error!("Event loop stopped");
+                    break;
+                }
+                Wake::Request(Some(request)) => {
+                    if config
+                        .mqtt_command_topic
+                        .as_ref()
+                        .is_none_or(|topic| request.topic != topic.as_bytes())
+                    {
+                        continue;
+                    }
+                    (Command::parse(&request.payload), Some(request))
+                }
+                Wake::Hangup => {
+                    info!("SIGHUP received");
+                    (Ok(Command::Reload), None)
+                }
+            };
+
+            let result = match command {
+                Ok(Command::ReadNow) => {
+                    let result = read_and_publish(
+                        &client,
+                        &reporting,
+                        &config,
+                        &mut sensor,
+                        &mut read_attempts,
+                        &mut captures,
+                        delay,
+                    )
+                    .await;
+                    match result {
+                        Ok(result) => result,
                         Err(e) => {
                             error!("Failed to publish data: {}", e);
                             break;
                         }
                     }
-                    sleep(delay).await;
                 }
-                Err(e) => {
-                    error!("Failed to read temperature and humidity: {:?}", e);
-                    sleep(err_read_delay).await;
+                Ok(Command::SetInterval(interval)) => {
+                    info!("Reading every {}s", interval.as_secs());
+                    delay = interval;
+                    if next_read.is_some() {
+                        next_read = Some(Instant::now() + delay);
+                    }
+                    Ok(json!({ "seconds": delay.as_secs() }))
+                }
+                Ok(Command::Pause) => {
+                    info!("Pausing readings");
+                    paused = true;
+                    next_read = None;
+                    Ok(Value::Null)
+                }
+                Ok(Command::Resume) => {
+                    info!("Resuming readings");
+                    paused = false;
+                    next_read = Some(Instant::now());
+                    Ok(Value::Null)
+                }
+                Ok(Command::Reload) => {
+                    info!("Reloading configuration");
+                    let previous_delay = config.delay;
+                    let previous_realtime = config.realtime;
+                    match reload_config(&client, &mut config, &mut client_config, &log_handle).await
+                    {
+                        Ok(needs_reconnect) => {
+                            sensor.close();
+                            if previous_realtime == Realtime::Off {
+                                lock_memory(&config);
+                            }
+                            if config.delay != previous_delay {
+                                info!("Reading every {}s", config.delay.as_secs());
+                                delay = config.delay;
+                                if next_read.is_some() {
+                                    next_read = Some(Instant::now() + delay);
+                                }
+                            }
+                            status.lock().unwrap().set_config(config.summary());
+                            reconnect = needs_reconnect;
+                            Ok(json!({ "reconnect": reconnect }))
+                        }
+                        Err(e) => {
+                            error!("Failed to reload the configuration: {}", e);
+                            Err(e.to_string())
+                        }
+                    }
+                }
+                Err(ref e) => {
+                    warn!("Invalid command: {e}");
+                    Err(e.clone())
                 }
             };
+
+            if let Some(request) = request {
+                reply(&client, &request, &response(command.ok(), result)).await;
+            }
+
+            if reconnect {
+                break;
+            }
+        }
+
+        if reconnect {
+            info!("Connection settings changed, reconnecting...");
+            // A clean disconnect doesn't send the last will, and the topic may change.
+            if let Err(e) = client
+                .publish(&availability_topic, QoS::AtLeastOnce, true, OFFLINE)
+                .await
+            {
+                debug!("Failed to publish the availability: {}", e);
+            }
+            if let Err(e) = client.disconnect().await {
+                debug!("Failed to disconnect: {}", e);
+            }
+            if timeout(Duration::from_secs(1), &mut event_loop_handle)
+                .await
+                .is_err()
+            {
+                event_loop_handle.abort();
+            }
+            continue;
         }
 
         if event_loop_handle.await.is_err() {
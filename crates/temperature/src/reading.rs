@@ -0,0 +1,400 @@
+//! Reading the configured sensor and turning the reading into MQTT messages.
+#[cfg(feature = "cdev")]
+use rpi_gpio::dht22::capture_line_async;
+use rpi_gpio::{
+    bme280::Bme280,
+    calibration::Calibrated,
+    dht22::{capture_async_with, decode_with, run_blocking, Decoded, Recovery},
+    iio::{IioDht, IIO_DEVICES},
+    metrics::Metrics,
+    onewire::{Ds18b20, W1_DEVICES},
+    realtime::RealtimeOptions,
+    record::{Capture, Recorder},
+    sensor::{self, Blocking, Measurements, Quantity, SensorError},
+    sht31::Sht31,
+    simulated::SimulatedDht22,
+    status::SharedStatus,
+    Backend, ReadingError,
+};
+use rppal::i2c::I2c;
+use rumqttc::v5::mqttbytes::v5::PublishProperties;
+use serde_json::{json, Value};
+use tokio::time::Instant;
+use tracing::{error, warn};
+
+use std::{io, sync::Arc, time::Duration};
+
+use crate::config::{Config, Sensor};
+
+/// Where the outcome of each reading is reported.
+pub struct Reporting {
+    pub status: SharedStatus,
+    pub metrics: Arc<Metrics>,
+    pub recorder: Option<Recorder>,
+}
+
+/// The sensor read through the [`sensor::Sensor`] interface, opened by the first reading and
+/// kept open until it fails with an I/O error or it's closed after a configuration change.
+#[derive(Default)]
+pub struct OpenedSensor(Option<(Source, Box<dyn sensor::Sensor + Send>)>);
+
+impl OpenedSensor {
+    /// Close the sensor, so that the next reading opens it with its configuration.
+    pub fn close(&mut self) {
+        self.0 = None;
+    }
+}
+
+/// What identifies a sensor, published as a user property: its pin, I2C bus and address, IIO
+/// device or ROM id.
+pub type Source = (&'static str, String);
+
+/// What the sensor measured, calibrated.
+pub enum Measured {
+    /// A reading of the sensor at `Source`, and the heuristics needed to decode it for the DHT22.
+    Reading(Source, Calibrated, Option<Recovery>),
+    /// Reading of each 1-Wire probe, by ROM id.
+    Probes(Vec<(String, Calibrated)>),
+}
+
+/// A message of a reading.
+pub struct Message {
+    /// ROM id of the probe, `None` for the sensor topic.
+    pub id: Option<String>,
+    pub source: Source,
+    pub payload: Value,
+    /// Payload of the measurements before calibration, if the sensor is calibrated.
+    pub raw: Option<Value>,
+}
+
+impl From<(Option<String>, Source, &Calibrated)> for Message {
+    fn from((id, source, calibrated): (Option<String>, Source, &Calibrated)) -> Self {
+        Self {
+            id,
+            source,
+            payload: payload(&calibrated.measurements),
+            raw: calibrated.raw.as_ref().map(payload),
+        }
+    }
+}
+
+impl Message {
+    /// The payload with the raw one under `raw`, for the status and the replies.
+    #[must_use]
+    pub fn data(&self) -> Value {
+        let mut data = self.payload.clone();
+        if let Some(raw) = &self.raw {
+            data["raw"] = raw.clone();
+        }
+        data
+    }
+
+    /// `properties` with the source of the message, and the raw payload as the `raw` user
+    /// property if the sensor is calibrated.
+    #[must_use]
+    pub fn properties(&self, properties: &PublishProperties) -> PublishProperties {
+        let mut properties = properties.clone();
+        let (key, value) = &self.source;
+        properties
+            .user_properties
+            .push(((*key).to_string(), value.clone()));
+        if let Some(raw) = &self.raw {
+            properties
+                .user_properties
+                .push(("raw".to_string(), raw.to_string()));
+        }
+        properties
+    }
+}
+
+impl Measured {
+    #[must_use]
+    pub const fn recovery(&self) -> Option<Recovery> {
+        match self {
+            Self::Reading(_, _, recovery) => *recovery,
+            Self::Probes(_) => None,
+        }
+    }
+
+    /// The messages to publish, on the sensor topic or on the subtopic of a probe.
+    #[must_use]
+    pub fn messages(&self) -> Vec<Message> {
+        match self {
+            Self::Reading(source, calibrated, _) => {
+                vec![Message::from((None, source.clone(), calibrated))]
+            }
+            Self::Probes(probes) => probes
+                .iter()
+                .map(|(id, calibrated)| {
+                    Message::from((Some(id.clone()), ("rom_id", id.clone()), calibrated))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Topic of a [`Message`], the subtopic named after `id` for a probe.
+#[must_use]
+pub fn topic(config: &Config, id: Option<&str>) -> String {
+    id.map_or_else(
+        || config.mqtt_topic.clone(),
+        |id| format!("{}/{id}", config.mqtt_topic),
+    )
+}
+
+/// The values of `measurements`, formatted with a decimal.
+#[must_use]
+pub fn payload(measurements: &Measurements) -> Value {
+    measurements
+        .iter()
+        .filter_map(|measurement| {
+            let value = measurement.number()?;
+            Some((measurement.quantity().key(), json!(format!("{value:.1}"))))
+        })
+        .collect()
+}
+
+/// Capture the sensor from userspace and decode the capture.
+async fn capture_and_decode(
+    config: &Config,
+    realtime: Option<RealtimeOptions>,
+    reporting: &Reporting,
+) -> Result<Decoded, ReadingError> {
+    let pin = config.pin.expect("required for the DHT22");
+    let captured = match config.backend {
+        Backend::Rppal => capture_async_with(pin, realtime).await,
+        #[cfg(feature = "cdev")]
+        Backend::Cdev => {
+            let line = config.line_spec().expect("validated when loading");
+            capture_line_async(line, realtime).await
+        }
+        Backend::Simulated => SimulatedDht22::new(config.simulation).capture(),
+    };
+    if let Some(recorder) = &reporting.recorder {
+        if let Some(capture) = Capture::new(pin, &captured) {
+            if let Err(e) = recorder.record(&capture) {
+                error!("Failed to record the capture: {}", e);
+            }
+        }
+    }
+    let result = captured.and_then(|pulses| decode_with(&pulses, config.recovery));
+    if !matches!(result, Err(ReadingError::Realtime(_))) {
+        reporting
+            .metrics
+            .capture_mode(realtime.is_some(), result.is_ok());
+    }
+    result
+}
+
+/// Open the sensor read through the [`sensor::Sensor`] interface: the kernel driver or an I2C
+/// sensor, along with its source.
+fn open_sensor(config: &Config) -> Result<(Source, Box<dyn sensor::Sensor + Send>), ReadingError> {
+    match config.sensor {
+        Sensor::Dht22Iio => {
+            let sensor = match &config.iio_device {
+                Some(device) => IioDht::new(device),
+                None => IioDht::find(IIO_DEVICES).map_err(ReadingError::Io)?,
+            };
+            let device = sensor.device().file_name().map_or_else(
+                || sensor.device().display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            Ok((("iio_device", device), Box::new(sensor)))
+        }
+        Sensor::Bme280 | Sensor::Sht31 => {
+            let address = config.i2c_address.expect("set for the I2C sensors");
+            let bus = I2c::with_bus(config.i2c_bus)?;
+            let sensor: Box<dyn sensor::Sensor + Send> = if config.sensor == Sensor::Bme280 {
+                Box::new(Blocking::new(Bme280::new(bus, address)?))
+            } else {
+                Box::new(Blocking::new(Sht31::new(bus, address)))
+            };
+            Ok((source(config), sensor))
+        }
+        Sensor::Dht22 | Sensor::Ds18b20 => unreachable!("read by capture or by probe"),
+    }
+}
+
+/// Read the sensor of `opened`, opening it on the blocking pool first if it isn't.
+async fn read_opened(config: &Config, opened: &mut OpenedSensor) -> Result<Measured, SensorError> {
+    let (source, mut sensor) = if let Some(open) = opened.0.take() {
+        open
+    } else {
+        let config = config.clone();
+        run_blocking(move || open_sensor(&config)).await?
+    };
+    let result = sensor.read().await;
+    // An I/O error may come from the bus or the device file, a timeout from the sensor only.
+    if !matches!(result, Err(SensorError::Reading(ReadingError::Io(_)))) {
+        opened.0 = Some((source.clone(), sensor));
+    }
+    Ok(calibrated(config, source, result?, None))
+}
+
+/// Read every DS18B20 probe, skipping the failing ones as long as another one answers.
+async fn read_probes() -> Result<Vec<(String, Measurements)>, SensorError> {
+    let probes = Ds18b20::probes(W1_DEVICES).map_err(ReadingError::Io)?;
+    let mut readings = Vec::with_capacity(probes.len());
+    let mut failure = None;
+    for mut probe in probes {
+        match sensor::Sensor::read(&mut probe).await {
+            Ok(measurements) => readings.push((probe.id(), measurements)),
+            Err(e) => {
+                warn!("Failed to read the probe {}: {}", probe.id(), e);
+                failure = Some(e);
+            }
+        }
+    }
+    match failure {
+        Some(e) if readings.is_empty() => Err(e),
+        None if readings.is_empty() => Err(SensorError::Reading(ReadingError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no DS18B20 probe in {W1_DEVICES}"),
+        )))),
+        _ => Ok(readings),
+    }
+}
+
+/// Calibrate a reading of the sensor at `source` with the calibration of its pin, bus and
+/// address or IIO device.
+fn calibrated(
+    config: &Config,
+    source: Source,
+    measurements: Measurements,
+    recovery: Option<Recovery>,
+) -> Measured {
+    let calibrated = config.calibrations.calibrate(&source.1, measurements);
+    Measured::Reading(source, calibrated, recovery)
+}
+
+/// Source of the DHT22, or `bus:address` for the I2C sensors.
+fn source(config: &Config) -> Source {
+    if config.sensor == Sensor::Dht22 {
+        let pin = config.pin.expect("required for the DHT22");
+        ("pin", pin.to_string())
+    } else {
+        let address = config.i2c_address.expect("set for the I2C sensors");
+        ("i2c", format!("{}:{address:#04x}", config.i2c_bus))
+    }
+}
+
+/// Read the configured sensor once, calibrate the reading and report the outcome. The sensors
+/// read through the [`sensor::Sensor`] interface are kept in `opened`.
+///
+/// # Errors
+/// Returns the error of the sensor, or of every 1-Wire probe if none answers.
+pub async fn read_sensor(
+    config: &Config,
+    realtime: Option<RealtimeOptions>,
+    reporting: &Reporting,
+    opened: &mut OpenedSensor,
+) -> Result<Measured, SensorError> {
+    let started = Instant::now();
+    let result = match config.sensor {
+        Sensor::Dht22 => capture_and_decode(config, realtime, reporting)
+            .await
+            .map(|decoded| {
+                calibrated(
+                    config,
+                    source(config),
+                    decoded.reading.into(),
+                    decoded.recovery,
+                )
+            })
+            .map_err(SensorError::from),
+        Sensor::Dht22Iio | Sensor::Bme280 | Sensor::Sht31 => read_opened(config, opened).await,
+        Sensor::Ds18b20 => read_probes().await.map(|probes| {
+            let probes = probes
+                .into_iter()
+                .map(|(id, measurements)| {
+                    let calibrated = config.calibrations.calibrate(&id, measurements);
+                    (id, calibrated)
+                })
+                .collect();
+            Measured::Probes(probes)
+        }),
+    };
+    reporting
+        .metrics
+        .read(started.elapsed(), result.as_ref().err());
+    match &result {
+        Ok(Measured::Reading(_, calibrated, _)) => {
+            reporting.metrics.measurements(&calibrated.measurements);
+        }
+        Ok(Measured::Probes(probes)) => {
+            for (id, calibrated) in probes {
+                if let Some(temperature) = calibrated.measurements.value(Quantity::Temperature) {
+                    reporting.metrics.probe_temperature(id, temperature);
+                }
+            }
+        }
+        Err(_) => {}
+    }
+    result
+}
+
+/// Expiry of the messages, once the next reading replaces them. `None` when readings are
+/// published back to back, a zero expiry would drop them right away.
+fn expiry(delay: Duration) -> Option<u32> {
+    (!delay.is_zero()).then(|| u32::try_from(delay.as_secs()).unwrap_or(u32::MAX))
+}
+
+/// Properties of the messages of a reading.
+#[must_use]
+pub fn publish_properties(
+    config: &Config,
+    read_attempts: u32,
+    delay: Duration,
+    recovery: Option<Recovery>,
+) -> PublishProperties {
+    let mut user_properties = vec![
+        (
+            "sensor_model".to_string(),
+            config.sensor.model().to_string(),
+        ),
+        (
+            "firmware_version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("read_attempts".to_string(), read_attempts.to_string()),
+    ];
+    if let Some(recovery) = recovery {
+        user_properties.push(("recovery".to_string(), recovery.to_string()));
+    }
+
+    PublishProperties {
+        payload_format_indicator: Some(1),
+        message_expiry_interval: expiry(delay),
+        content_type: Some("application/json".to_string()),
+        user_properties,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expiry, Measured};
+
+    use rpi_gpio::sensor::{Measurement, Measurements};
+    use rumqttc::v5::mqttbytes::v5::PublishProperties;
+
+    use std::time::Duration;
+
+    #[test]
+    fn source_property() {
+        let measurements: Measurements = std::iter::once(Measurement::Temperature(21.0)).collect();
+        let measured = Measured::Probes(vec![("28-0123".to_string(), measurements.into())]);
+        let properties = measured.messages()[0].properties(&PublishProperties::default());
+        assert_eq!(
+            properties.user_properties,
+            [("rom_id".to_string(), "28-0123".to_string())]
+        );
+    }
+
+    #[test]
+    fn message_expiry() {
+        assert_eq!(expiry(Duration::from_secs(60)), Some(60));
+        assert_eq!(expiry(Duration::from_secs(u64::MAX)), Some(u32::MAX));
+        assert_eq!(expiry(Duration::ZERO), None);
+    }
+}
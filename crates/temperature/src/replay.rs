@@ -0,0 +1,74 @@
+use rpi_gpio::{
+    dht22::{analyze, decode_with, DecodeOptions, Pulses},
+    record::load,
+};
+
+use std::{collections::BTreeMap, error::Error};
+
+/// Decode recorded captures again and print how each of them was classified.
+///
+/// # Errors
+/// Returns the error if a file can't be loaded.
+pub fn run(paths: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut outcomes: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut total = 0;
+
+    for path in paths {
+        for capture in load(path)? {
+            total += 1;
+            let analysis = analyze(&capture.pulses);
+            let outcome = match &analysis.result {
+                Ok(reading) => {
+                    println!(
+                        "{} pin {}: ok, {:.1}°C {:.1}%",
+                        capture.timestamp, capture.pin, reading.temperature, reading.humidity
+                    );
+                    "ok"
+                }
+                Err(e) => {
+                    println!("{} pin {}: {e}", capture.timestamp, capture.pin);
+                    e.kind()
+                }
+            };
+            *outcomes.entry(outcome).or_default() += 1;
+
+            if analysis.result.is_err() {
+                let recovered = <&Pulses>::try_from(capture.pulses.as_slice())
+                    .ok()
+                    .and_then(|pulses| decode_with(pulses, DecodeOptions::ALL).ok());
+                if let Some(decoded) = recovered {
+                    let recovery = decoded.recovery.unwrap_or_default();
+                    println!(
+                        "  recoverable with {recovery}: {:.1}°C {:.1}%",
+                        decoded.reading.temperature, decoded.reading.humidity
+                    );
+                }
+            }
+
+            if let Some(recorded) = &capture.error {
+                if analysis.result.is_ok() {
+                    println!("  recorded as failed: {recorded}");
+                }
+            }
+
+            let margins: Vec<String> = analysis
+                .margins
+                .iter()
+                .map(|margin| format!("{margin:+}"))
+                .collect();
+            println!("  threshold: {}", analysis.threshold);
+            println!("  margins: {}", margins.join(" "));
+            if let Some((bit, margin)) = analysis.weakest_bit() {
+                println!("  weakest bit: {bit} ({margin:+})");
+            }
+        }
+    }
+
+    let summary: Vec<String> = outcomes
+        .iter()
+        .map(|(outcome, count)| format!("{count} {outcome}"))
+        .collect();
+    println!("{total} captures: {}", summary.join(", "));
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! The temperature service on the simulated backend, against an embedded broker.
+use std::time::Duration;
+
+use harness::{Broker, Certificates, Proxy, Publish, Service, Subscriber};
+use serde_json::Value;
+
+const TOPIC: &str = "test/temperature";
+const AVAILABILITY: &str = "test/temperature/availability";
+const WAIT: Duration = Duration::from_secs(15);
+/// Long enough for the service to notice the cut and wait before reconnecting.
+const RECONNECT: Duration = Duration::from_secs(30);
+
+fn temperature(host: &str, port: u16, extra: &[(&str, String)]) -> Service {
+    let mut vars = vec![
+        ("MQTT_IP", host.to_string()),
+        ("MQTT_PORT", port.to_string()),
+        ("MQTT_USERNAME", "user".to_string()),
+        ("MQTT_PASSWORD", "password".to_string()),
+        ("TEMPERATURE_MQTT_CLIENT_ID", format!("temperature-{port}")),
+        ("TEMPERATURE_MQTT_TOPIC", TOPIC.to_string()),
+        ("TEMPERATURE_MQTT_DELAY", "1".to_string()),
+        ("TEMPERATURE_DHT_PIN", "4".to_string()),
+        ("TEMPERATURE_GPIO_BACKEND", "simulated".to_string()),
+    ];
+    vars.extend(extra.iter().cloned());
+    Service::start(env!("CARGO_BIN_EXE_temperature"), &vars)
+}
+
+fn json(publish: &Publish) -> Value {
+    serde_json::from_slice(&publish.payload).unwrap()
+}
+
+fn assert_reading(publish: &Publish) {
+    let data = json(publish);
+    let value = |key: &str| data[key].as_str().unwrap().parse::<f32>().unwrap();
+    assert!((-40.0..=80.0).contains(&value("temperature")), "{data}");
+    assert!((0.0..=100.0).contains(&value("humidity")), "{data}");
+    assert!(!publish.retain);
+}
+
+#[tokio::test]
+async fn publishes_readings() {
+    let broker = Broker::start();
+    let mut subscriber = Subscriber::connect(broker.port, "test/temperature/#").await;
+    let _service = temperature("127.0.0.1", broker.port, &[]);
+
+    let availability = subscriber.next_on(AVAILABILITY, WAIT).await.unwrap();
+    assert_eq!(&availability.payload[..], b"online");
+
+    let first = subscriber.next_on(TOPIC, WAIT).await.unwrap();
+    assert_reading(&first);
+    let second = subscriber.next_on(TOPIC, WAIT).await.unwrap();
+    assert_reading(&second);
+}
+
+#[tokio::test]
+async fn availability() {
+    let broker = Broker::start();
+    let mut service = temperature("127.0.0.1", broker.port, &[]);
+    {
+        let mut subscriber = Subscriber::connect(broker.port, TOPIC).await;
+        subscriber.next_on(TOPIC, WAIT).await.unwrap();
+    }
+
+    // Subscribing afterwards, the availability is retained.
+    let mut subscriber = Subscriber::connect(broker.port, AVAILABILITY).await;
+    let online = subscriber.next(WAIT).await.unwrap();
+    assert_eq!(&online.payload[..], b"online");
+    assert!(online.retain);
+
+    service.kill();
+    let offline = subscriber.next(WAIT).await.unwrap();
+    assert_eq!(&offline.payload[..], b"offline");
+
+    let mut late = Subscriber::connect(broker.port, AVAILABILITY).await;
+    let retained = late.next(WAIT).await.unwrap();
+    assert_eq!(&retained.payload[..], b"offline");
+    assert!(retained.retain);
+}
+
+/// Stands in for a broker restart: the embedded broker can't be stopped and started again on the
+/// same port, so the proxy cuts the connection instead. The service notices it the same way and
+/// has to reconnect and announce itself again, but the broker keeps its retained messages.
+#[tokio::test]
+async fn reconnects_after_a_cut() {
+    let broker = Broker::start();
+    let proxy = Proxy::start(broker.port).await;
+    let mut subscriber = Subscriber::connect(broker.port, "test/temperature/#").await;
+    let _service = temperature("127.0.0.1", proxy.port, &[]);
+    subscriber.next_on(TOPIC, WAIT).await.unwrap();
+
+    proxy.cut();
+    let offline = subscriber.next_on(AVAILABILITY, WAIT).await.unwrap();
+    assert_eq!(&offline.payload[..], b"offline");
+
+    let online = subscriber.next_on(AVAILABILITY, RECONNECT).await.unwrap();
+    assert_eq!(&online.payload[..], b"online");
+    assert_reading(&subscriber.next_on(TOPIC, WAIT).await.unwrap());
+}
+
+#[tokio::test]
+async fn tls() {
+    let certificates = Certificates::generate();
+    let broker = Broker::start_tls(&certificates);
+    let tls_port = broker.tls_port.unwrap();
+    let mut subscriber = Subscriber::connect_tls(tls_port, &certificates, TOPIC).await;
+    let _service = temperature(
+        "localhost",
+        tls_port,
+        &[(
+            "CERTIFICATE_AUTHORITY_PATH",
+            certificates.ca.display().to_string(),
+        )],
+    );
+
+    assert_reading(&subscriber.next(WAIT).await.unwrap());
+}